@@ -0,0 +1,251 @@
+//! Verification of the [JWT](https://developer.atlassian.com/hipchat/guide/jwt) HipChat sends with
+//! add-on iframe and webhook requests
+//!
+//! Tokens are signed with HS256 using the installation's `oauth_secret`, and
+//! `iss` holds its `oauth_id`. Use `issuer` to find the installation, then
+//! `verify` or `verify_request` with its secret.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Deserializer};
+use serde_json::{self, Value};
+use sha2::{Digest, Sha256};
+use url::form_urlencoded;
+
+use error::Error;
+
+/// Tokens are accepted for this long after `exp`, to allow for clock skew
+const EXPIRY_LEEWAY: u64 = 30;
+
+#[derive(Debug, Clone, Default, Hash, Eq, PartialEq, Deserialize)]
+pub struct Context {
+    pub room_id: Option<u64>,
+    pub user_tz: Option<String>
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Deserialize)]
+pub struct Claims {
+    /// The `oauth_id` of the installation
+    pub iss: String,
+    /// The id of the user making the request
+    #[serde(default, deserialize_with = "string_or_number")]
+    pub sub: Option<String>,
+    pub exp: u64,
+    pub iat: Option<u64>,
+    /// Query string hash, binding the token to one request
+    pub qsh: Option<String>,
+    #[serde(default)]
+    pub context: Context
+}
+
+impl Claims {
+    pub fn user_id(&self) -> Option<&str> {
+        self.sub.as_deref()
+    }
+    pub fn room_id(&self) -> Option<u64> {
+        self.context.room_id
+    }
+}
+
+#[derive(Deserialize)]
+struct Header {
+    alg: String
+}
+
+fn string_or_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    Ok(match try!(Option::<Value>::deserialize(deserializer)) {
+        Some(Value::String(s)) => Some(s),
+        Some(Value::Number(n)) => Some(n.to_string()),
+        _ => None
+    })
+}
+
+fn invalid<T>(reason: &str) -> Result<T, Error> {
+    Err(Error::InvalidToken(reason.to_owned()))
+}
+
+fn decode_part<T: for<'de> Deserialize<'de>>(part: &str) -> Result<T, Error> {
+    let bytes = match base64::decode_config(part, base64::URL_SAFE_NO_PAD) {
+        Ok(bytes) => bytes,
+        Err(_) => return invalid("malformed base64")
+    };
+    match serde_json::from_slice(&bytes) {
+        Ok(value) => Ok(value),
+        Err(_) => invalid("malformed JSON")
+    }
+}
+
+fn split(token: &str) -> Result<(&str, &str, &str), Error> {
+    let parts: Vec<&str> = token.split('.').collect();
+    if parts.len() != 3 {
+        return invalid("expected three segments");
+    }
+    Ok((parts[0], parts[1], parts[2]))
+}
+
+/// The unverified `iss` claim, for looking up the installation whose secret signed the token
+pub fn issuer(token: &str) -> Result<String, Error> {
+    let (_, payload, _) = try!(split(token));
+    let claims: Claims = try!(decode_part(payload));
+    Ok(claims.iss)
+}
+
+/// Checks the signature and expiry of a token and returns its claims
+pub fn verify(token: &str, secret: &str) -> Result<Claims, Error> {
+    let (header, payload, signature) = try!(split(token));
+    let header: Header = try!(decode_part(header));
+    if header.alg != "HS256" {
+        return Err(Error::InvalidToken(format!("unsupported algorithm {}", header.alg)));
+    }
+
+    let signature = match base64::decode_config(signature, base64::URL_SAFE_NO_PAD) {
+        Ok(signature) => signature,
+        Err(_) => return invalid("malformed signature")
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(&token.as_bytes()[..token.rfind('.').unwrap()]);
+    if mac.verify_slice(&signature).is_err() {
+        return invalid("signature mismatch");
+    }
+
+    let claims: Claims = try!(decode_part(payload));
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    if claims.exp.saturating_add(EXPIRY_LEEWAY) < now {
+        return invalid("token expired");
+    }
+    Ok(claims)
+}
+
+/// Verifies a token sent with a request, including its `qsh` claim
+///
+/// `path` is relative to the add-on's base URL and `query` is the raw query
+/// string, which may still contain the `jwt` parameter. Tokens without a
+/// `qsh` claim are rejected; use `verify` for callbacks known to omit it.
+pub fn verify_request(token: &str, secret: &str, method: &str, path: &str, query: &str) -> Result<Claims, Error> {
+    let claims = try!(verify(token, secret));
+    match claims.qsh {
+        Some(ref qsh) if *qsh == query_string_hash(method, path, query) => (),
+        Some(_) => return invalid("query string hash mismatch"),
+        None => return invalid("missing query string hash")
+    }
+    Ok(claims)
+}
+
+/// The `qsh` claim for a request: the hex SHA-256 of its canonical form
+pub fn query_string_hash(method: &str, path: &str, query: &str) -> String {
+    Sha256::digest(canonical_request(method, path, query).as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn canonical_request(method: &str, path: &str, query: &str) -> String {
+    let mut path = path.trim_end_matches('/').replace('&', "%26");
+    if !path.starts_with('/') {
+        path.insert(0, '/');
+    }
+
+    let mut params: Vec<(String, Vec<String>)> = Vec::new();
+    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+        if key == "jwt" {
+            continue;
+        }
+        let key = percent_encode(&key);
+        let value = percent_encode(&value);
+        match params.iter().position(|(k, _)| *k == key) {
+            Some(i) => params[i].1.push(value),
+            None => params.push((key, vec![value]))
+        }
+    }
+    params.sort();
+    let query: Vec<String> = params.into_iter().map(|(key, mut values)| {
+        values.sort();
+        format!("{}={}", key, values.join(","))
+    }).collect();
+
+    format!("{}&{}&{}", method.to_uppercase(), path, query.join("&"))
+}
+
+/// RFC 3986 percent-encoding, leaving only unreserved characters as is
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for b in value.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(b as char),
+            b => encoded.push_str(&format!("%{:02X}", b))
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sign(claims: &str, secret: &str) -> String {
+        let header = base64::encode_config(r#"{"alg":"HS256","typ":"JWT"}"#, base64::URL_SAFE_NO_PAD);
+        let payload = base64::encode_config(claims, base64::URL_SAFE_NO_PAD);
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(format!("{}.{}", header, payload).as_bytes());
+        let signature = base64::encode_config(mac.finalize().into_bytes(), base64::URL_SAFE_NO_PAD);
+        format!("{}.{}.{}", header, payload, signature)
+    }
+
+    fn exp() -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() + 300
+    }
+
+    #[test]
+    fn unit_verify_returns_claims() {
+        let token = sign(&format!(r#"{{"iss":"abc","sub":42,"exp":{},"context":{{"room_id":7,"user_tz":"UTC"}}}}"#, exp()), "secret");
+        assert_eq!(issuer(&token).unwrap(), "abc");
+        let claims = verify(&token, "secret").unwrap();
+        assert_eq!(claims.user_id(), Some("42"));
+        assert_eq!(claims.room_id(), Some(7));
+    }
+
+    #[test]
+    fn unit_verify_rejects_bad_tokens() {
+        let token = sign(&format!(r#"{{"iss":"abc","exp":{}}}"#, exp()), "secret");
+        assert!(verify(&token, "other").is_err());
+        assert!(verify("abc.def", "secret").is_err());
+
+        let expired = sign(r#"{"iss":"abc","exp":1000}"#, "secret");
+        match verify(&expired, "secret") {
+            Err(Error::InvalidToken(ref reason)) if reason == "token expired" => (),
+            other => panic!("expected expired token error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn unit_verify_accepts_max_expiry() {
+        let token = sign(r#"{"iss":"abc","exp":18446744073709551615}"#, "secret");
+        assert_eq!(verify(&token, "secret").unwrap().exp, u64::max_value());
+    }
+
+    #[test]
+    fn unit_canonical_request() {
+        assert_eq!(
+            canonical_request("get", "/glance/", "jwt=x&b=2&a=z%20y&a=x&c=%2A"),
+            "GET&/glance&a=x,z%20y&b=2&c=%2A"
+        );
+        assert_eq!(canonical_request("POST", "", ""), "POST&/&");
+    }
+
+    #[test]
+    fn unit_verify_request_checks_qsh() {
+        let qsh = query_string_hash("GET", "/glance", "room=7");
+        let token = sign(&format!(r#"{{"iss":"abc","exp":{},"qsh":"{}"}}"#, exp(), qsh), "secret");
+        assert!(verify_request(&token, "secret", "GET", "/glance", &format!("room=7&jwt={}", token)).is_ok());
+        assert!(verify_request(&token, "secret", "GET", "/glance", "room=8").is_err());
+        assert!(verify_request(&token, "secret", "POST", "/glance", "room=7").is_err());
+
+        let without_qsh = sign(&format!(r#"{{"iss":"abc","exp":{}}}"#, exp()), "secret");
+        match verify_request(&without_qsh, "secret", "GET", "/glance", "room=7") {
+            Err(Error::InvalidToken(ref reason)) if reason == "missing query string hash" => (),
+            other => panic!("expected missing qsh error, got {:?}", other)
+        }
+    }
+}
@@ -0,0 +1,403 @@
+//! [HipChat Connect](https://developer.atlassian.com/hipchat/guide) add-on support
+//!
+//! `Addon` serves the capabilities descriptor, records installations from the
+//! install callback in an `InstallationStore`, and hands out a `Client`
+//! authenticated as each installation.
+
+pub mod jwt;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use serde_json::{self, Value};
+use url::Url;
+
+use auth::{Scope, TokenRequest};
+use client::{Client, ClientBuilder};
+use error::Error;
+use glance::GlanceIcon;
+
+/// Text shown to users, such as a glance name
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct LocalizedText {
+    pub value: String
+}
+
+impl<'a> From<&'a str> for LocalizedText {
+    fn from(value: &'a str) -> Self {
+        LocalizedText { value: value.to_owned() }
+    }
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct DescriptorLinks {
+    #[serde(rename = "self")]
+    pub self_: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub homepage: Option<String>
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Vendor {
+    pub name: String,
+    pub url: String
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiConsumer {
+    pub scopes: Vec<Scope>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from_name: Option<String>
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Installable {
+    /// Receives the install callback, and a `DELETE` to `{callbackUrl}/{oauthId}` on uninstall
+    pub callback_url: String,
+    pub allow_room: bool,
+    pub allow_global: bool
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct WebhookCapability {
+    pub url: String,
+    /// E.g. `room_message` or `room_enter`
+    pub event: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authentication: Option<String>
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GlanceCapability {
+    pub key: String,
+    pub name: LocalizedText,
+    pub icon: GlanceIcon,
+    /// Returns the glance's initial content
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query_url: Option<String>,
+    /// Key of the web panel or dialog opened when the glance is clicked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct WebPanelCapability {
+    pub key: String,
+    pub name: LocalizedText,
+    /// E.g. `hipchat.sidebar.right`
+    pub location: String,
+    pub url: String
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DialogCapability {
+    pub key: String,
+    pub title: LocalizedText,
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub options: Option<Value>
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DescriptorCapabilities {
+    pub hipchat_api_consumer: ApiConsumer,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub installable: Option<Installable>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub webhook: Vec<WebhookCapability>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub glance: Vec<GlanceCapability>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub web_panel: Vec<WebPanelCapability>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dialog: Vec<DialogCapability>
+}
+
+/// The [capabilities descriptor](https://developer.atlassian.com/hipchat/guide/capabilities-descriptor) served to HipChat
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Descriptor {
+    pub key: String,
+    pub name: String,
+    pub description: String,
+    pub links: DescriptorLinks,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vendor: Option<Vendor>,
+    pub capabilities: DescriptorCapabilities
+}
+
+impl Descriptor {
+    /// A descriptor served from `self_url` that requests `scopes` and has no other capabilities
+    pub fn new<K: Into<String>, N: Into<String>, U: Into<String>>(key: K, name: N, self_url: U, scopes: Vec<Scope>) -> Self {
+        Descriptor {
+            key: key.into(),
+            name: name.into(),
+            description: String::new(),
+            links: DescriptorLinks { self_: self_url.into(), homepage: None },
+            vendor: None,
+            capabilities: DescriptorCapabilities {
+                hipchat_api_consumer: ApiConsumer { scopes: scopes, from_name: None },
+                installable: None,
+                webhook: Vec::new(),
+                glance: Vec::new(),
+                web_panel: Vec::new(),
+                dialog: Vec::new()
+            }
+        }
+    }
+    pub fn description<T: Into<String>>(mut self, description: T) -> Self {
+        self.description = description.into();
+        self
+    }
+    /// Makes the add-on installable in rooms and globally, with install callbacks sent to `callback_url`
+    pub fn installable<T: Into<String>>(mut self, callback_url: T) -> Self {
+        self.capabilities.installable = Some(Installable { callback_url: callback_url.into(), allow_room: true, allow_global: true });
+        self
+    }
+    pub fn webhook(mut self, webhook: WebhookCapability) -> Self {
+        self.capabilities.webhook.push(webhook);
+        self
+    }
+    pub fn glance(mut self, glance: GlanceCapability) -> Self {
+        self.capabilities.glance.push(glance);
+        self
+    }
+    pub fn web_panel(mut self, web_panel: WebPanelCapability) -> Self {
+        self.capabilities.web_panel.push(web_panel);
+        self
+    }
+    pub fn dialog(mut self, dialog: DialogCapability) -> Self {
+        self.capabilities.dialog.push(dialog);
+        self
+    }
+    pub fn scopes(&self) -> &[Scope] {
+        &self.capabilities.hipchat_api_consumer.scopes
+    }
+}
+
+/// The body HipChat posts to the install callback
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Installation {
+    pub oauth_id: String,
+    pub oauth_secret: String,
+    pub capabilities_url: String,
+    /// Set when the add-on was installed in a single room
+    pub room_id: Option<u64>,
+    pub group_id: u64
+}
+
+impl Installation {
+    pub fn parse(body: &str) -> Result<Self, Error> {
+        let installation: Installation = try!(serde_json::from_str(body));
+        Ok(installation)
+    }
+    /// The API origin of the HipChat server that installed the add-on, taken from `capabilities_url`
+    pub fn origin(&self) -> Result<String, Error> {
+        match Url::parse(&self.capabilities_url) {
+            Ok(ref url) if url.has_host() => Ok(url.origin().ascii_serialization()),
+            _ => Err(Error::Validation(format!("invalid capabilities URL {:?}", self.capabilities_url)))
+        }
+    }
+    /// A client that acquires tokens for `scopes` with this installation's OAuth credentials
+    pub fn client(&self, scopes: Vec<Scope>) -> Result<Client, Error> {
+        let origin = try!(self.origin());
+        ClientBuilder::new()
+            .oauth(self.oauth_id.as_str(), self.oauth_secret.as_str(), TokenRequest::client_credentials(scopes.clone()))
+            .origin(origin)
+            .scopes(scopes)
+            .build()
+    }
+}
+
+/// Where installations are kept between restarts, keyed by `oauth_id`
+pub trait InstallationStore: Send + Sync {
+    fn save(&self, installation: &Installation) -> Result<(), Error>;
+    fn load(&self, oauth_id: &str) -> Result<Option<Installation>, Error>;
+    fn remove(&self, oauth_id: &str) -> Result<(), Error>;
+}
+
+/// Keeps installations in memory, for tests and single-process deployments
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    installations: Mutex<HashMap<String, Installation>>
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+}
+
+impl InstallationStore for MemoryStore {
+    fn save(&self, installation: &Installation) -> Result<(), Error> {
+        self.installations.lock().unwrap().insert(installation.oauth_id.clone(), installation.clone());
+        Ok(())
+    }
+    fn load(&self, oauth_id: &str) -> Result<Option<Installation>, Error> {
+        Ok(self.installations.lock().unwrap().get(oauth_id).cloned())
+    }
+    fn remove(&self, oauth_id: &str) -> Result<(), Error> {
+        self.installations.lock().unwrap().remove(oauth_id);
+        Ok(())
+    }
+}
+
+/// A Connect add-on: its descriptor, its installations and a client for each
+pub struct Addon {
+    descriptor: Descriptor,
+    store: Box<InstallationStore>,
+    clients: Mutex<HashMap<String, Arc<Client>>>
+}
+
+impl Addon {
+    pub fn new<S: InstallationStore + 'static>(descriptor: Descriptor, store: S) -> Self {
+        Addon {
+            descriptor: descriptor,
+            store: Box::new(store),
+            clients: Mutex::new(HashMap::new())
+        }
+    }
+    pub fn descriptor(&self) -> &Descriptor {
+        &self.descriptor
+    }
+    /// The descriptor as served to HipChat
+    pub fn descriptor_json(&self) -> String {
+        serde_json::to_string(&self.descriptor).unwrap()
+    }
+    /// Handles the install callback body and stores the installation
+    pub fn install(&self, body: &str) -> Result<Installation, Error> {
+        let installation = try!(Installation::parse(body));
+        try!(installation.origin());
+        try!(self.store.save(&installation));
+        self.clients.lock().unwrap().remove(&installation.oauth_id);
+        Ok(installation)
+    }
+    /// Handles the uninstall callback for `oauth_id`
+    pub fn uninstall(&self, oauth_id: &str) -> Result<(), Error> {
+        self.clients.lock().unwrap().remove(oauth_id);
+        self.store.remove(oauth_id)
+    }
+    pub fn installation(&self, oauth_id: &str) -> Result<Option<Installation>, Error> {
+        self.store.load(oauth_id)
+    }
+    /// Verifies the JWT sent with an add-on request against the issuing installation's secret
+    pub fn authenticate(&self, token: &str, method: &str, path: &str, query: &str) -> Result<(Installation, jwt::Claims), Error> {
+        let oauth_id = try!(jwt::issuer(token));
+        let installation = match try!(self.store.load(&oauth_id)) {
+            Some(installation) => installation,
+            None => return Err(Error::InvalidToken(format!("unknown installation {:?}", oauth_id)))
+        };
+        let claims = try!(jwt::verify_request(token, &installation.oauth_secret, method, path, query));
+        Ok((installation, claims))
+    }
+    /// A client for the installation, limited to the scopes in the descriptor.
+    /// Clients are reused so that tokens are only fetched when they expire
+    pub fn client(&self, oauth_id: &str) -> Result<Arc<Client>, Error> {
+        if let Some(client) = self.clients.lock().unwrap().get(oauth_id) {
+            return Ok(client.clone());
+        }
+        let installation = match try!(self.store.load(oauth_id)) {
+            Some(installation) => installation,
+            None => return Err(Error::Validation(format!("unknown installation {:?}", oauth_id)))
+        };
+        let client = Arc::new(try!(installation.client(self.descriptor.scopes().to_vec())));
+        self.clients.lock().unwrap().insert(oauth_id.to_owned(), client.clone());
+        Ok(client)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_server::{self, TestServer};
+
+    fn descriptor() -> Descriptor {
+        Descriptor::new("com.example.builds", "Builds", "https://addon.example.com/capabilities", vec![Scope::SendNotification, Scope::ViewRoom])
+            .description("Build health")
+            .installable("https://addon.example.com/installed")
+            .webhook(WebhookCapability {
+                url: "https://addon.example.com/webhook".to_owned(),
+                event: "room_message".to_owned(),
+                pattern: Some("^/build".to_owned()),
+                name: None,
+                authentication: Some("jwt".to_owned())
+            })
+            .glance(GlanceCapability {
+                key: "build-health".to_owned(),
+                name: "Builds".into(),
+                icon: GlanceIcon { url: "https://addon.example.com/icon.png".to_owned(), url_2x: "https://addon.example.com/icon@2x.png".to_owned() },
+                query_url: Some("https://addon.example.com/glance".to_owned()),
+                target: Some("build-panel".to_owned())
+            })
+            .web_panel(WebPanelCapability {
+                key: "build-panel".to_owned(),
+                name: "Builds".into(),
+                location: "hipchat.sidebar.right".to_owned(),
+                url: "https://addon.example.com/panel".to_owned()
+            })
+    }
+
+    fn installation_json(origin: &str) -> String {
+        format!(r#"{{"oauthId": "abc", "oauthSecret": "secret", "capabilitiesUrl": "{}/v2/capabilities", "roomId": 7, "groupId": 1}}"#, origin)
+    }
+
+    #[test]
+    fn unit_serialize_descriptor() {
+        let actual: Value = serde_json::from_str(&Addon::new(descriptor(), MemoryStore::new()).descriptor_json()).unwrap();
+        let capabilities = &actual["capabilities"];
+        assert_eq!(actual["links"]["self"], "https://addon.example.com/capabilities");
+        assert_eq!(capabilities["hipchatApiConsumer"]["scopes"], json_array(&["send_notification", "view_room"]));
+        assert_eq!(capabilities["installable"]["callbackUrl"], "https://addon.example.com/installed");
+        assert_eq!(capabilities["installable"]["allowRoom"], true);
+        assert_eq!(capabilities["webhook"][0]["authentication"], "jwt");
+        assert_eq!(capabilities["glance"][0]["queryUrl"], "https://addon.example.com/glance");
+        assert_eq!(capabilities["glance"][0]["icon"]["url@2x"], "https://addon.example.com/icon@2x.png");
+        assert_eq!(capabilities["webPanel"][0]["location"], "hipchat.sidebar.right");
+        assert!(capabilities.get("dialog").is_none());
+    }
+
+    fn json_array(values: &[&str]) -> Value {
+        Value::Array(values.iter().map(|value| Value::String(value.to_string())).collect())
+    }
+
+    #[test]
+    fn unit_parse_installation() {
+        let installation = Installation::parse(&installation_json("https://api.hipchat.com")).unwrap();
+        assert_eq!(installation.room_id, Some(7));
+        assert_eq!(installation.origin().unwrap(), "https://api.hipchat.com");
+
+        let global = Installation::parse(r#"{"oauthId": "abc", "oauthSecret": "secret", "capabilitiesUrl": "https://hipchat.example.com/v2/capabilities", "groupId": 1}"#).unwrap();
+        assert_eq!(global.room_id, None);
+    }
+
+    #[test]
+    fn unit_addon_install_and_client() {
+        let server = TestServer::new(vec![
+            test_server::json(r#"{"access_token": "installation-token", "expires_in": 3600, "scope": "send_notification view_room", "group_id": 1}"#),
+            test_server::response("204 No Content", &[], "")
+        ]);
+        let addon = Addon::new(descriptor(), MemoryStore::new());
+        addon.install(&installation_json(&server.origin)).unwrap();
+
+        let client = addon.client("abc").unwrap();
+        client.send_notification("7", &::room::Notification::builder().message("green").build().unwrap()).unwrap();
+        assert!(Arc::ptr_eq(&client, &addon.client("abc").unwrap()));
+
+        let token_request = server.requests.recv().unwrap();
+        assert_eq!(token_request.path, "/v2/oauth/token");
+        let notification = server.requests.recv().unwrap();
+        assert_eq!(notification.header("Authorization"), Some("Bearer installation-token"));
+
+        addon.uninstall("abc").unwrap();
+        assert!(addon.installation("abc").unwrap().is_none());
+        assert!(addon.client("abc").is_err());
+    }
+}
@@ -0,0 +1,449 @@
+use std::fmt::{self, Display, Formatter};
+use std::io::prelude::*;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use hyper::Client as HyperClient;
+use hyper::header::{Authorization, Basic, ContentType};
+use hyper::status::StatusClass;
+use serde_json::{self};
+use url::form_urlencoded::Serializer;
+
+use client::ClientBuilder;
+use error::Error;
+use user::UserDetailLinks;
+
+/// Tokens are refreshed this long before HipChat says they expire
+const EXPIRY_MARGIN: u64 = 60;
+
+/// [API scopes](https://www.hipchat.com/docs/apiv2/auth#scopes) a token can be granted
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    AdminGroup,
+    AdminRoom,
+    ImportData,
+    ManageRooms,
+    SendMessage,
+    SendNotification,
+    ViewGroup,
+    ViewMessages,
+    ViewRoom
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Scope::AdminGroup => "admin_group",
+            Scope::AdminRoom => "admin_room",
+            Scope::ImportData => "import_data",
+            Scope::ManageRooms => "manage_rooms",
+            Scope::SendMessage => "send_message",
+            Scope::SendNotification => "send_notification",
+            Scope::ViewGroup => "view_group",
+            Scope::ViewMessages => "view_messages",
+            Scope::ViewRoom => "view_room"
+        }
+    }
+}
+
+impl Display for Scope {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "admin_group" => Ok(Scope::AdminGroup),
+            "admin_room" => Ok(Scope::AdminRoom),
+            "import_data" => Ok(Scope::ImportData),
+            "manage_rooms" => Ok(Scope::ManageRooms),
+            "send_message" => Ok(Scope::SendMessage),
+            "send_notification" => Ok(Scope::SendNotification),
+            "view_group" => Ok(Scope::ViewGroup),
+            "view_messages" => Ok(Scope::ViewMessages),
+            "view_room" => Ok(Scope::ViewRoom),
+            _ => Err(Error::Validation(format!("unknown scope {:?}", s)))
+        }
+    }
+}
+
+/// Parses a space separated scope list, skipping scopes this crate does not know
+pub fn parse_scopes(scope: &str) -> Vec<Scope> {
+    scope.split_whitespace().filter_map(|s| s.parse().ok()).collect()
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GrantType {
+    ClientCredentials,
+    Password,
+    RefreshToken
+}
+
+impl GrantType {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            GrantType::ClientCredentials => "client_credentials",
+            GrantType::Password => "password",
+            GrantType::RefreshToken => "refresh_token"
+        }
+    }
+}
+
+/// [Generate token](https://www.hipchat.com/docs/apiv2/method/generate_token)
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub struct TokenRequest {
+    pub grant_type: GrantType,
+    pub scope: Vec<Scope>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub refresh_token: Option<String>
+}
+
+impl TokenRequest {
+    pub fn client_credentials(scope: Vec<Scope>) -> Self {
+        TokenRequest {
+            grant_type: GrantType::ClientCredentials,
+            scope: scope,
+            username: None,
+            password: None,
+            refresh_token: None
+        }
+    }
+    pub fn password<U: Into<String>, P: Into<String>>(username: U, password: P, scope: Vec<Scope>) -> Self {
+        TokenRequest {
+            grant_type: GrantType::Password,
+            scope: scope,
+            username: Some(username.into()),
+            password: Some(password.into()),
+            refresh_token: None
+        }
+    }
+    pub fn refresh_token<T: Into<String>>(refresh_token: T, scope: Vec<Scope>) -> Self {
+        TokenRequest {
+            grant_type: GrantType::RefreshToken,
+            scope: scope,
+            username: None,
+            password: None,
+            refresh_token: Some(refresh_token.into())
+        }
+    }
+    /// Encodes the request as an `application/x-www-form-urlencoded` body
+    pub fn to_form(&self) -> String {
+        let mut form = Serializer::new(String::new());
+        form.append_pair("grant_type", self.grant_type.as_str());
+        if !self.scope.is_empty() {
+            let scope: Vec<&str> = self.scope.iter().map(Scope::as_str).collect();
+            form.append_pair("scope", &scope.join(" "));
+        }
+        self.username.as_ref().map(|username| form.append_pair("username", username));
+        self.password.as_ref().map(|password| form.append_pair("password", password));
+        self.refresh_token.as_ref().map(|refresh_token| form.append_pair("refresh_token", refresh_token));
+        form.finish()
+    }
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Deserialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub expires_in: u64,
+    pub scope: String,
+    pub group_id: u64,
+    pub group_name: Option<String>,
+    pub token_type: Option<String>,
+    pub refresh_token: Option<String>
+}
+
+impl TokenResponse {
+    pub fn scopes(&self) -> Vec<Scope> {
+        parse_scopes(&self.scope)
+    }
+}
+
+/// [Generate token](https://www.hipchat.com/docs/apiv2/method/generate_token)
+///
+/// `origin` is the same origin given to `Client::new`, e.g. `https://api.hipchat.com`.
+pub fn request_token(hyper_client: &HyperClient, origin: &str, client_id: &str, client_secret: &str, req: &TokenRequest) -> Result<TokenResponse, Error> {
+    let body = req.to_form();
+    let mut res = try!(hyper_client.post(&format!("{}/v2/oauth/token", origin))
+        .header(Authorization(Basic { username: client_id.to_owned(), password: Some(client_secret.to_owned()) }))
+        .header(ContentType::form_url_encoded())
+        .body(body.as_bytes())
+        .send());
+
+    if res.status.class() != StatusClass::Success {
+        return Err(Error::HttpStatus(res.status));
+    }
+
+    let mut body = String::new();
+    try!(res.read_to_string(&mut body));
+    let token: TokenResponse = try!(serde_json::from_str(&body));
+    Ok(token)
+}
+
+/// [Get session](https://www.hipchat.com/docs/apiv2/method/get_session)
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Deserialize)]
+pub struct Session {
+    pub id: String,
+    pub access_token: Option<String>,
+    pub expires_in: Option<u64>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    pub owner: Option<SessionOwner>,
+    pub group: Option<SessionGroup>,
+    pub client: Option<SessionClient>
+}
+
+impl Session {
+    /// The granted scopes that this crate recognizes
+    pub fn scopes(&self) -> Vec<Scope> {
+        self.scopes.iter().filter_map(|s| s.parse().ok()).collect()
+    }
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes().contains(&scope)
+    }
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Deserialize)]
+pub struct SessionOwner {
+    pub id: u64,
+    pub name: String,
+    pub mention_name: Option<String>,
+    pub links: Option<UserDetailLinks>
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Deserialize)]
+pub struct SessionGroup {
+    pub id: u64,
+    pub name: String
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Deserialize)]
+pub struct SessionClient {
+    pub id: String,
+    #[serde(default)]
+    pub allowed_scopes: Vec<String>
+}
+
+/// Supplies the bearer token sent with each API request
+pub trait CredentialProvider: Send + Sync {
+    /// Returns a token that is currently valid, fetching a new one if needed
+    fn token(&self) -> Result<String, Error>;
+    /// Discards the current token after HipChat rejected it. Returns `true`
+    /// if a retry may succeed with a freshly fetched token
+    fn invalidate(&self) -> bool {
+        false
+    }
+}
+
+/// A fixed bearer token, such as a personal access token
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub struct StaticToken(pub String);
+
+impl CredentialProvider for StaticToken {
+    fn token(&self) -> Result<String, Error> {
+        Ok(self.0.clone())
+    }
+}
+
+#[derive(Debug)]
+struct CachedToken {
+    response: TokenResponse,
+    expires_at: Instant
+}
+
+/// Acquires tokens from `/v2/oauth/token` and refreshes them before they expire
+pub struct OAuthCredentials {
+    origin: String,
+    client_id: String,
+    client_secret: String,
+    request: TokenRequest,
+    hyper_client: HyperClient,
+    cached: Mutex<Option<CachedToken>>
+}
+
+impl OAuthCredentials {
+    /// Fetches tokens with `ClientBuilder`'s default timeouts. Use `ClientBuilder::oauth`
+    /// instead to share a client's proxy, timeouts and root certificates
+    pub fn new<O: Into<String>, I: Into<String>, S: Into<String>>(origin: O, client_id: I, client_secret: S, request: TokenRequest) -> Result<Self, Error> {
+        let hyper_client = try!(ClientBuilder::new().hyper_client());
+        Ok(OAuthCredentials::with_hyper_client(origin, client_id, client_secret, request, hyper_client))
+    }
+    pub fn with_hyper_client<O: Into<String>, I: Into<String>, S: Into<String>>(origin: O, client_id: I, client_secret: S, request: TokenRequest, hyper_client: HyperClient) -> Self {
+        OAuthCredentials {
+            origin: origin.into().trim_end_matches('/').to_owned(),
+            client_id: client_id.into(),
+            client_secret: client_secret.into(),
+            request: request,
+            hyper_client: hyper_client,
+            cached: Mutex::new(None)
+        }
+    }
+    /// Returns the most recent token response, fetching one if none is cached
+    ///
+    /// Expired tokens are refreshed with their refresh token when they have one,
+    /// falling back to the configured grant if the refresh is refused.
+    pub fn token_response(&self) -> Result<TokenResponse, Error> {
+        let mut cached = self.cached.lock().unwrap();
+        let fresh = match *cached {
+            Some(ref token) => Instant::now() < token.expires_at,
+            None => false
+        };
+        if !fresh {
+            let refresh = match *cached {
+                Some(CachedToken { response: TokenResponse { refresh_token: Some(ref refresh_token), .. }, .. }) => {
+                    Some(TokenRequest::refresh_token(refresh_token.as_str(), self.request.scope.clone()))
+                }
+                _ => None
+            };
+            let refreshed = refresh.filter(|refresh| *refresh != self.request).map(|refresh| self.request_token(&refresh));
+            let response = match refreshed {
+                Some(Ok(response)) => response,
+                _ => try!(self.request_token(&self.request))
+            };
+            let lifetime = response.expires_in.saturating_sub(EXPIRY_MARGIN);
+            *cached = Some(CachedToken {
+                expires_at: Instant::now() + Duration::from_secs(lifetime),
+                response: response
+            });
+        }
+        Ok(cached.as_ref().unwrap().response.clone())
+    }
+    fn request_token(&self, request: &TokenRequest) -> Result<TokenResponse, Error> {
+        request_token(&self.hyper_client, &self.origin, &self.client_id, &self.client_secret, request)
+    }
+}
+
+impl CredentialProvider for OAuthCredentials {
+    fn token(&self) -> Result<String, Error> {
+        self.token_response().map(|response| response.access_token)
+    }
+    /// Expires the cached token. Returns `false` if there was no unexpired
+    /// token, as then a retry would only repeat the request that just failed
+    fn invalidate(&self) -> bool {
+        let mut cached = self.cached.lock().unwrap();
+        match *cached {
+            Some(ref mut token) if Instant::now() < token.expires_at => {
+                token.expires_at = Instant::now();
+                true
+            }
+            _ => false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_server::{self, TestServer};
+
+    const TOKEN_BODY: &str = r#"{
+        "access_token": "5c6bNmHK4JPfO6PFC6HNbjKyK0ASbhpcuTx6U2Ym",
+        "expires_in": 3599,
+        "group_id": 1,
+        "group_name": "Example",
+        "scope": "send_notification view_group",
+        "token_type": "bearer"
+    }"#;
+
+    #[test]
+    fn unit_token_request_to_form() {
+        let req = TokenRequest::password("user@example.com", "p@ss word", vec![Scope::SendMessage, Scope::ViewRoom]);
+        assert_eq!(req.to_form(), "grant_type=password&scope=send_message+view_room&username=user%40example.com&password=p%40ss+word");
+    }
+
+    #[test]
+    fn unit_token_response_scopes() {
+        let token: TokenResponse = serde_json::from_str(TOKEN_BODY).unwrap();
+        assert_eq!(token.scopes(), vec![Scope::SendNotification, Scope::ViewGroup]);
+        assert_eq!(token.refresh_token, None);
+    }
+
+    #[test]
+    fn unit_oauth_credentials_caches_token() {
+        let server = TestServer::new(vec![test_server::json(TOKEN_BODY)]);
+        let credentials = OAuthCredentials::new(server.origin.clone(), "id", "secret",
+                                                TokenRequest::client_credentials(vec![Scope::SendNotification])).unwrap();
+        assert_eq!(credentials.token().unwrap(), "5c6bNmHK4JPfO6PFC6HNbjKyK0ASbhpcuTx6U2Ym");
+        assert_eq!(credentials.token().unwrap(), "5c6bNmHK4JPfO6PFC6HNbjKyK0ASbhpcuTx6U2Ym");
+
+        let req = server.requests.recv().unwrap();
+        assert_eq!(req.method, "POST");
+        assert_eq!(req.path, "/v2/oauth/token");
+        assert_eq!(req.header("Authorization"), Some("Basic aWQ6c2VjcmV0"));
+        assert_eq!(req.body, "grant_type=client_credentials&scope=send_notification");
+        assert!(server.requests.try_recv().is_err());
+    }
+
+    #[test]
+    fn unit_oauth_credentials_refreshes_after_invalidate() {
+        let refreshable = TOKEN_BODY.replace("\"token_type\"", "\"refresh_token\": \"r1\", \"token_type\"");
+        let second = TOKEN_BODY.replace("5c6bNmHK4JPfO6PFC6HNbjKyK0ASbhpcuTx6U2Ym", "second");
+        let server = TestServer::new(vec![test_server::json(&refreshable), test_server::json(&second)]);
+        let credentials = OAuthCredentials::new(server.origin.clone(), "id", "secret",
+                                                TokenRequest::client_credentials(vec![])).unwrap();
+        credentials.token().unwrap();
+        assert!(credentials.invalidate());
+        assert!(!credentials.invalidate());
+        assert_eq!(credentials.token().unwrap(), "second");
+
+        server.requests.recv().unwrap();
+        let req = server.requests.recv().unwrap();
+        assert_eq!(req.body, "grant_type=refresh_token&refresh_token=r1");
+    }
+
+    #[test]
+    fn unit_oauth_credentials_falls_back_when_refresh_fails() {
+        let refreshable = TOKEN_BODY.replace("\"token_type\"", "\"refresh_token\": \"r1\", \"token_type\"");
+        let second = TOKEN_BODY.replace("5c6bNmHK4JPfO6PFC6HNbjKyK0ASbhpcuTx6U2Ym", "second");
+        let server = TestServer::new(vec![
+            test_server::json(&refreshable),
+            test_server::response("400 Bad Request", &[("Content-Type", "application/json")], r#"{"error": "invalid_grant"}"#),
+            test_server::json(&second)
+        ]);
+        let credentials = OAuthCredentials::new(server.origin.clone(), "id", "secret",
+                                                TokenRequest::client_credentials(vec![])).unwrap();
+        assert!(!credentials.invalidate());
+        credentials.token().unwrap();
+        assert!(credentials.invalidate());
+        assert_eq!(credentials.token().unwrap(), "second");
+
+        let bodies: Vec<String> = server.requests.iter().take(3).map(|request| request.body).collect();
+        assert_eq!(bodies, vec!["grant_type=client_credentials", "grant_type=refresh_token&refresh_token=r1", "grant_type=client_credentials"]);
+    }
+
+    #[test]
+    fn unit_scope_round_trip() {
+        assert_eq!(serde_json::to_string(&Scope::AdminRoom).unwrap(), "\"admin_room\"");
+        assert_eq!("view_messages".parse::<Scope>().unwrap(), Scope::ViewMessages);
+        assert!("launch_rockets".parse::<Scope>().is_err());
+        assert_eq!(parse_scopes("view_room launch_rockets send_message"), vec![Scope::ViewRoom, Scope::SendMessage]);
+    }
+
+    #[test]
+    fn unit_deserialize_session() {
+        let session: Session = serde_json::from_str(r#"{
+            "id": "5c6bNmHK4JPfO6PFC6HNbjKyK0ASbhpcuTx6U2Ym",
+            "access_token": "5c6bNmHK4JPfO6PFC6HNbjKyK0ASbhpcuTx6U2Ym",
+            "expires_in": 3142,
+            "scopes": ["send_notification", "view_room", "brand_new_scope"],
+            "owner": {
+                "id": 25699,
+                "name": "Build Bot",
+                "mention_name": "buildbot",
+                "links": { "self": "https://api.hipchat.com/v2/user/25699" }
+            },
+            "group": { "id": 1, "name": "Example" },
+            "client": { "id": "a3d4c2d8", "allowed_scopes": ["send_notification"] }
+        }"#).unwrap();
+        assert_eq!(session.scopes(), vec![Scope::SendNotification, Scope::ViewRoom]);
+        assert!(!session.has_scope(Scope::AdminRoom));
+        assert_eq!(session.owner.unwrap().mention_name, Some("buildbot".to_owned()));
+    }
+}
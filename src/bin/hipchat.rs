@@ -0,0 +1,425 @@
+extern crate clap;
+extern crate hipchat_client;
+#[macro_use]
+extern crate serde_derive;
+
+extern crate serde;
+extern crate serde_json;
+
+use std::env;
+use std::error::Error as StdError;
+use std::fs::File;
+use std::io::{self, Read};
+use std::process;
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use serde::Serialize;
+
+use hipchat_client::{Client, ClientBuilder};
+use hipchat_client::message::{Color, MessageFormat, MessagesRequest};
+use hipchat_client::room::{Notification, RoomUpdate, RoomsRequest};
+use hipchat_client::user::UsersRequest;
+use hipchat_client::util::Privacy;
+
+type CliResult = Result<(), Box<StdError>>;
+
+/// The same shape as `example.settings.json`; other fields are ignored
+#[derive(Debug, Default, Deserialize)]
+struct Config {
+    token: Option<String>,
+    origin: Option<String>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Output {
+    Table,
+    Json
+}
+
+fn app<'a, 'b>() -> App<'a, 'b> {
+    let room = Arg::with_name("room").required(true).help("Room id or name");
+    let message = Arg::with_name("message").help("Message text, read from stdin when omitted or -");
+    let max_results = Arg::with_name("max-results").long("max-results").takes_value(true);
+
+    App::new("hipchat")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Everyday HipChat operations.\n\nThe token and origin are read from HIPCHAT_TOKEN and HIPCHAT_ORIGIN, \
+                falling back to a JSON config file like example.settings.json.")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(Arg::with_name("config").long("config").short("c").takes_value(true).global(true)
+            .help("Config file with token and origin [env: HIPCHAT_CONFIG]"))
+        .arg(Arg::with_name("output").long("output").short("o").takes_value(true).global(true)
+            .possible_values(&["table", "json"]).default_value("table"))
+        .subcommand(SubCommand::with_name("rooms")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("list")
+                .about("List rooms")
+                .arg(Arg::with_name("private").long("private").help("Include private rooms"))
+                .arg(Arg::with_name("archived").long("archived").help("Include archived rooms"))
+                .arg(max_results.clone())))
+        .subcommand(SubCommand::with_name("room")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("show")
+                .about("Show room details")
+                .arg(room.clone()))
+            .subcommand(SubCommand::with_name("update")
+                .about("Update a room, keeping unspecified settings")
+                .arg(room.clone())
+                .arg(Arg::with_name("name").long("name").takes_value(true))
+                .arg(Arg::with_name("topic").long("topic").takes_value(true))
+                .arg(Arg::with_name("privacy").long("privacy").takes_value(true).possible_values(&["public", "private"]))
+                .arg(Arg::with_name("archived").long("archived").takes_value(true).possible_values(&["true", "false"]))
+                .arg(Arg::with_name("guest-access").long("guest-access").takes_value(true).possible_values(&["true", "false"]))))
+        .subcommand(SubCommand::with_name("notify")
+            .about("Send a room notification")
+            .arg(room.clone())
+            .arg(message.clone())
+            .arg(Arg::with_name("color").long("color").takes_value(true)
+                .possible_values(&["yellow", "green", "red", "purple", "gray", "random"]).default_value("yellow"))
+            .arg(Arg::with_name("format").long("format").takes_value(true)
+                .possible_values(&["html", "text"]).default_value("html"))
+            .arg(Arg::with_name("notify").long("notify").help("Alert room members")))
+        .subcommand(SubCommand::with_name("message")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("send")
+                .about("Send a message to a room")
+                .arg(room.clone())
+                .arg(message.clone())))
+        .subcommand(SubCommand::with_name("users")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("list")
+                .about("List users")
+                .arg(Arg::with_name("guests").long("guests").help("Include guests"))
+                .arg(Arg::with_name("deleted").long("deleted").help("Include deleted users"))
+                .arg(max_results.clone())))
+        .subcommand(SubCommand::with_name("history")
+            .about("Show room history")
+            .arg(room.clone())
+            .arg(Arg::with_name("since").long("since").takes_value(true).help("Earliest date to include, ISO-8601"))
+            .arg(max_results.clone()))
+}
+
+fn main() {
+    let matches = app().get_matches();
+    if let Err(e) = run(&matches) {
+        eprintln!("hipchat: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(matches: &ArgMatches) -> CliResult {
+    let client = try!(client(matches));
+    let output = match matches.value_of("output") {
+        Some("json") => Output::Json,
+        _ => Output::Table
+    };
+
+    match matches.subcommand() {
+        ("rooms", Some(m)) => match m.subcommand() {
+            ("list", Some(m)) => rooms_list(&client, m, output),
+            _ => unreachable!()
+        },
+        ("room", Some(m)) => match m.subcommand() {
+            ("show", Some(m)) => room_show(&client, m, output),
+            ("update", Some(m)) => room_update(&client, m),
+            _ => unreachable!()
+        },
+        ("notify", Some(m)) => notify(&client, m),
+        ("message", Some(m)) => match m.subcommand() {
+            ("send", Some(m)) => message_send(&client, m, output),
+            _ => unreachable!()
+        },
+        ("users", Some(m)) => match m.subcommand() {
+            ("list", Some(m)) => users_list(&client, m, output),
+            _ => unreachable!()
+        },
+        ("history", Some(m)) => history(&client, m, output),
+        _ => unreachable!()
+    }
+}
+
+fn client(matches: &ArgMatches) -> Result<Client, Box<StdError>> {
+    let path = matches.value_of("config").map(str::to_owned).or_else(|| env::var("HIPCHAT_CONFIG").ok());
+    let mut config = match path {
+        Some(path) => {
+            let mut contents = String::new();
+            try!(try!(File::open(&path).map_err(|e| format!("{}: {}", path, e))).read_to_string(&mut contents));
+            let config: Config = try!(serde_json::from_str(&contents).map_err(|e| format!("{}: {}", path, e)));
+            config
+        }
+        None => Config::default()
+    };
+    if let Ok(token) = env::var("HIPCHAT_TOKEN") {
+        config.token = Some(token);
+    }
+    if let Ok(origin) = env::var("HIPCHAT_ORIGIN") {
+        config.origin = Some(origin);
+    }
+
+    let token = try!(config.token.ok_or("no token; set HIPCHAT_TOKEN or use --config"));
+    let mut builder = ClientBuilder::new().token(token).user_agent(concat!("hipchat-cli/", env!("CARGO_PKG_VERSION")));
+    if let Some(origin) = config.origin {
+        builder = builder.origin(origin);
+    }
+    Ok(try!(builder.build()))
+}
+
+fn max_results(matches: &ArgMatches) -> Result<Option<u64>, Box<StdError>> {
+    match matches.value_of("max-results") {
+        Some(n) => Ok(Some(try!(n.parse().map_err(|_| format!("invalid --max-results {:?}", n))))),
+        None => Ok(None)
+    }
+}
+
+/// Uses the message argument, or stdin when it is missing or `-`
+fn message_text(matches: &ArgMatches) -> Result<String, Box<StdError>> {
+    match matches.value_of("message") {
+        Some(message) if message != "-" => Ok(message.to_owned()),
+        _ => {
+            let mut message = String::new();
+            try!(io::stdin().read_to_string(&mut message));
+            Ok(message.trim_end_matches(&['\r', '\n'][..]).to_owned())
+        }
+    }
+}
+
+fn print_json<T: Serialize>(value: &T) -> CliResult {
+    println!("{}", try!(serde_json::to_string_pretty(value)));
+    Ok(())
+}
+
+fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let line = |cells: Vec<&str>| {
+        let padded: Vec<String> = cells.iter().zip(&widths).map(|(cell, &width)| format!("{:1$}", cell, width)).collect();
+        println!("{}", padded.join("  ").trim_end());
+    };
+    line(headers.to_vec());
+    for row in rows {
+        line(row.iter().map(|cell| cell.as_str()).collect());
+    }
+}
+
+fn rooms_request(matches: &ArgMatches) -> Result<RoomsRequest, Box<StdError>> {
+    Ok(RoomsRequest {
+        start_index: None,
+        max_results: try!(max_results(matches)),
+        include_private: Some(matches.is_present("private")),
+        include_archived: Some(matches.is_present("archived"))
+    })
+}
+
+fn rooms_list(client: &Client, matches: &ArgMatches, output: Output) -> CliResult {
+    let req = try!(rooms_request(matches));
+    let rooms = try!(client.get_rooms(Some(&req)));
+    if output == Output::Json {
+        return print_json(&rooms.items);
+    }
+    let rows: Vec<Vec<String>> = rooms.items.iter().map(|room| vec![room.id.to_string(), room.name.clone()]).collect();
+    print_table(&["ID", "NAME"], &rows);
+    Ok(())
+}
+
+fn room_show(client: &Client, matches: &ArgMatches, output: Output) -> CliResult {
+    let room = try!(client.get_room(matches.value_of("room").unwrap()));
+    if output == Output::Json {
+        return print_json(&room);
+    }
+    let privacy = match room.privacy {
+        Privacy::Public => "public",
+        Privacy::Private => "private"
+    };
+    print_table(&["FIELD", "VALUE"], &[
+        vec!["id".to_owned(), room.id.to_string()],
+        vec!["name".to_owned(), room.name.clone()],
+        vec!["topic".to_owned(), room.topic.clone()],
+        vec!["privacy".to_owned(), privacy.to_owned()],
+        vec!["archived".to_owned(), room.is_archived.to_string()],
+        vec!["guest_accessible".to_owned(), room.is_guest_accessible.to_string()],
+        vec!["created".to_owned(), room.created.clone()],
+        vec!["xmpp_jid".to_owned(), room.xmpp_jid.clone()]
+    ]);
+    Ok(())
+}
+
+/// Only the settings given on the command line, for `Client::patch_room`
+fn room_patch(matches: &ArgMatches) -> Result<RoomUpdate, Box<StdError>> {
+    let mut builder = RoomUpdate::builder();
+    if let Some(name) = matches.value_of("name") {
+        builder = builder.name(name);
+    }
+    if let Some(topic) = matches.value_of("topic") {
+        builder = builder.topic(topic);
+    }
+    if let Some(privacy) = matches.value_of("privacy") {
+        builder = builder.privacy(if privacy == "private" { Privacy::Private } else { Privacy::Public });
+    }
+    if let Some(archived) = matches.value_of("archived") {
+        builder = builder.is_archived(archived == "true");
+    }
+    if let Some(guest_access) = matches.value_of("guest-access") {
+        builder = builder.is_guest_accessible(guest_access == "true");
+    }
+    Ok(try!(builder.build()))
+}
+
+fn room_update(client: &Client, matches: &ArgMatches) -> CliResult {
+    let patch = try!(room_patch(matches));
+    try!(client.patch_room(matches.value_of("room").unwrap(), &patch));
+    Ok(())
+}
+
+fn notification(matches: &ArgMatches, message: String) -> Result<Notification, Box<StdError>> {
+    let color = match matches.value_of("color").unwrap() {
+        "green" => Color::Green,
+        "red" => Color::Red,
+        "purple" => Color::Purple,
+        "gray" => Color::Gray,
+        "random" => Color::Random,
+        _ => Color::Yellow
+    };
+    let message_format = match matches.value_of("format").unwrap() {
+        "text" => MessageFormat::Text,
+        _ => MessageFormat::Html
+    };
+    Ok(try!(Notification::builder()
+        .color(color)
+        .message_format(message_format)
+        .notify(matches.is_present("notify"))
+        .message(message)
+        .build()))
+}
+
+fn notify(client: &Client, matches: &ArgMatches) -> CliResult {
+    let notification = try!(notification(matches, try!(message_text(matches))));
+    try!(client.send_notification(matches.value_of("room").unwrap(), &notification));
+    Ok(())
+}
+
+fn message_send(client: &Client, matches: &ArgMatches, output: Output) -> CliResult {
+    let res = try!(client.send_message(matches.value_of("room").unwrap(), try!(message_text(matches))));
+    if output == Output::Json {
+        return print_json(&res);
+    }
+    print_table(&["ID", "TIMESTAMP"], &[vec![res.id, res.timestamp]]);
+    Ok(())
+}
+
+fn users_request(matches: &ArgMatches) -> Result<UsersRequest, Box<StdError>> {
+    Ok(UsersRequest {
+        start_index: None,
+        max_results: try!(max_results(matches)),
+        include_guests: Some(matches.is_present("guests")),
+        include_deleted: Some(matches.is_present("deleted"))
+    })
+}
+
+fn users_list(client: &Client, matches: &ArgMatches, output: Output) -> CliResult {
+    let req = try!(users_request(matches));
+    let users = try!(client.get_users(Some(&req)));
+    if output == Output::Json {
+        return print_json(&users.items);
+    }
+    let rows: Vec<Vec<String>> = users.items.iter()
+        .map(|user| vec![user.id.to_string(), user.name.clone(), format!("@{}", user.mention_name)])
+        .collect();
+    print_table(&["ID", "NAME", "MENTION"], &rows);
+    Ok(())
+}
+
+fn history_request(matches: &ArgMatches) -> Result<MessagesRequest, Box<StdError>> {
+    Ok(MessagesRequest {
+        max_results: try!(max_results(matches)),
+        end_date: matches.value_of("since").map(str::to_owned),
+        ..MessagesRequest::default()
+    })
+}
+
+fn history(client: &Client, matches: &ArgMatches, output: Output) -> CliResult {
+    let req = try!(history_request(matches));
+    let messages = try!(client.get_room_history(matches.value_of("room").unwrap(), Some(&req)));
+    if output == Output::Json {
+        return print_json(&messages.items);
+    }
+    let rows: Vec<Vec<String>> = messages.items.iter()
+        .map(|message| {
+            let from = message.from.as_ref().map(|user| user.name.clone())
+                .or_else(|| message.from_name.clone())
+                .unwrap_or_default();
+            vec![message.date.clone(), from, message.message.replace('\n', " ")]
+        })
+        .collect();
+    print_table(&["DATE", "FROM", "MESSAGE"], &rows);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn matches<'a>(args: &[&str]) -> ArgMatches<'a> {
+        let mut argv = vec!["hipchat"];
+        argv.extend_from_slice(args);
+        app().get_matches_from_safe(argv).unwrap()
+    }
+
+    /// The matches of the innermost subcommand
+    fn subcommand<'a, 'b>(mut matches: &'b ArgMatches<'a>) -> &'b ArgMatches<'a> {
+        while let (_, Some(m)) = matches.subcommand() {
+            matches = m;
+        }
+        matches
+    }
+
+    #[test]
+    fn unit_cli_requires_subcommand_and_room() {
+        assert!(app().get_matches_from_safe(vec!["hipchat"]).is_err());
+        assert!(app().get_matches_from_safe(vec!["hipchat", "room", "show"]).is_err());
+        assert!(app().get_matches_from_safe(vec!["hipchat", "notify", "Ops", "--color", "blue"]).is_err());
+    }
+
+    #[test]
+    fn unit_cli_room_update_sends_only_given_settings() {
+        let m = matches(&["room", "update", "Ops", "--topic", "Deploy freeze", "--guest-access", "false"]);
+        let patch = room_patch(subcommand(&m)).unwrap();
+        assert_eq!(patch, RoomUpdate {
+            topic: Some("Deploy freeze".to_owned()),
+            is_guest_accessible: Some(false),
+            ..RoomUpdate::default()
+        });
+    }
+
+    #[test]
+    fn unit_cli_room_update_validates_name() {
+        let m = matches(&["room", "update", "Ops", "--name", ""]);
+        assert!(room_patch(subcommand(&m)).is_err());
+    }
+
+    #[test]
+    fn unit_cli_notification() {
+        let m = matches(&["-o", "json", "notify", "Ops", "deployed", "--color", "green", "--format", "text", "--notify"]);
+        assert_eq!(m.value_of("output"), Some("json"));
+        let notification = notification(subcommand(&m), "deployed".to_owned()).unwrap();
+        assert_eq!(notification.color, Color::Green);
+        assert_eq!(notification.message_format, MessageFormat::Text);
+        assert!(notification.notify);
+    }
+
+    #[test]
+    fn unit_cli_list_requests() {
+        let m = matches(&["rooms", "list", "--private", "--max-results", "50"]);
+        let req = rooms_request(subcommand(&m)).unwrap();
+        assert_eq!((req.max_results, req.include_private, req.include_archived), (Some(50), Some(true), Some(false)));
+
+        let m = matches(&["users", "list", "--guests", "--max-results", "many"]);
+        assert!(users_request(subcommand(&m)).is_err());
+
+        let m = matches(&["history", "Ops", "--since", "2017-03-01T00:00:00Z"]);
+        assert_eq!(history_request(subcommand(&m)).unwrap().end_date, Some("2017-03-01T00:00:00Z".to_owned()));
+    }
+}
@@ -0,0 +1,312 @@
+//! Dispatches `/command` and `@mention` messages to registered handlers
+//!
+//! Messages can come from history polling (`Bot::handle`) or from a
+//! `room_message` webhook (`Bot::handle_event`). Handlers only see a
+//! `Context` and return `Reply` values, so they can be tested without a
+//! `Client` by calling `Bot::dispatch`.
+
+use client::Client;
+use error::Error;
+use message::{Message, MessageType};
+use parse::{MessageParser, Token};
+use room::Notification;
+use user::UserDetail;
+
+/// What a handler was triggered by, and the text that followed the trigger
+#[derive(Debug)]
+pub struct Context<'a> {
+    pub room: &'a str,
+    pub message: &'a Message,
+    /// The command name without the leading `/`, or `None` for mentions
+    pub command: Option<&'a str>,
+    pub args: &'a str
+}
+
+impl<'a> Context<'a> {
+    pub fn sender(&self) -> Option<&'a UserDetail> {
+        self.message.from.as_ref()
+    }
+    pub fn args(&self) -> Vec<&'a str> {
+        self.args.split_whitespace().collect()
+    }
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub enum Reply {
+    Message(String),
+    Notification(Notification)
+}
+
+impl Reply {
+    pub fn message<T: Into<String>>(message: T) -> Self {
+        Reply::Message(message.into())
+    }
+    pub fn notification(notification: Notification) -> Self {
+        Reply::Notification(notification)
+    }
+}
+
+pub trait Handler: Send + Sync {
+    fn handle(&self, ctx: &Context) -> Result<Option<Reply>, Error>;
+}
+
+impl<F> Handler for F where F: Fn(&Context) -> Result<Option<Reply>, Error> + Send + Sync {
+    fn handle(&self, ctx: &Context) -> Result<Option<Reply>, Error> {
+        self(ctx)
+    }
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+enum Trigger {
+    Command(String),
+    Mention
+}
+
+struct Route {
+    trigger: Trigger,
+    rooms: Option<Vec<String>>,
+    handler: Box<Handler>
+}
+
+impl Route {
+    fn applies_to(&self, rooms: &[&str]) -> bool {
+        match self.rooms {
+            Some(ref allowed) => allowed.iter().any(|allowed| rooms.iter().any(|room| allowed.eq_ignore_ascii_case(room))),
+            None => true
+        }
+    }
+}
+
+/// The room of a `room_message` webhook event
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct WebhookRoom {
+    pub id: u64,
+    pub name: String
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RoomMessageItem {
+    pub message: Message,
+    pub room: WebhookRoom
+}
+
+/// The payload HipChat posts for a [room_message webhook](https://www.hipchat.com/docs/apiv2/webhooks#room_message)
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RoomMessageEvent {
+    pub event: String,
+    pub item: RoomMessageItem,
+    pub oauth_client_id: Option<String>,
+    pub webhook_id: Option<u64>
+}
+
+/// Routes messages to handlers. The first registered handler that matches a message wins
+#[derive(Default)]
+pub struct Bot {
+    mention_name: Option<String>,
+    routes: Vec<Route>
+}
+
+impl Bot {
+    pub fn new() -> Self {
+        Bot::default()
+    }
+    /// Sets the bot's own `@mention_name`, used for mention triggers and to ignore its own messages
+    pub fn with_mention_name<T: Into<String>>(mention_name: T) -> Self {
+        Bot {
+            mention_name: Some(mention_name.into().trim_start_matches('@').to_owned()),
+            routes: Vec::new()
+        }
+    }
+    /// Handles `/name args` in any room
+    pub fn command<T: Into<String>, H: Handler + 'static>(&mut self, name: T, handler: H) -> &mut Self {
+        self.route(Trigger::Command(name.into()), None, handler)
+    }
+    /// Handles `/name args` only in the given rooms, matched by id or name
+    pub fn command_in<T: Into<String>, H: Handler + 'static>(&mut self, name: T, rooms: &[&str], handler: H) -> &mut Self {
+        let rooms = rooms.iter().map(|room| room.to_string()).collect();
+        self.route(Trigger::Command(name.into()), Some(rooms), handler)
+    }
+    /// Handles messages that start with an `@mention` of the bot, in any room
+    pub fn mention<H: Handler + 'static>(&mut self, handler: H) -> &mut Self {
+        self.route(Trigger::Mention, None, handler)
+    }
+    /// Handles messages that start with an `@mention` of the bot, only in the given rooms
+    pub fn mention_in<H: Handler + 'static>(&mut self, rooms: &[&str], handler: H) -> &mut Self {
+        let rooms = rooms.iter().map(|room| room.to_string()).collect();
+        self.route(Trigger::Mention, Some(rooms), handler)
+    }
+    fn route<H: Handler + 'static>(&mut self, trigger: Trigger, rooms: Option<Vec<String>>, handler: H) -> &mut Self {
+        let trigger = match trigger {
+            Trigger::Command(name) => Trigger::Command(name.trim_start_matches('/').to_lowercase()),
+            trigger => trigger
+        };
+        self.routes.push(Route {
+            trigger: trigger,
+            rooms: rooms,
+            handler: Box::new(handler)
+        });
+        self
+    }
+    /// Runs the matching handler for a message posted in `room` and returns its reply
+    pub fn dispatch(&self, room: &str, message: &Message) -> Result<Option<Reply>, Error> {
+        self.dispatch_in(room, &[room], message)
+    }
+    fn dispatch_in(&self, room: &str, room_ids: &[&str], message: &Message) -> Result<Option<Reply>, Error> {
+        if message.message_type != MessageType::Message || self.is_own_message(message) {
+            return Ok(None);
+        }
+        let text = message.message.trim();
+        let command = parse_command(text);
+        let mention_args = self.mention_name.as_ref().and_then(|name| strip_mention(text, name));
+
+        for route in self.routes.iter().filter(|route| route.applies_to(room_ids)) {
+            let ctx = match (&route.trigger, command, mention_args) {
+                (Trigger::Command(name), Some((command, args)), _) if command.eq_ignore_ascii_case(name) => {
+                    Context { room: room, message: message, command: Some(command), args: args }
+                }
+                (Trigger::Mention, _, Some(args)) => {
+                    Context { room: room, message: message, command: None, args: args }
+                }
+                _ => continue
+            };
+            return route.handler.handle(&ctx);
+        }
+        Ok(None)
+    }
+    /// Dispatches a message from `room` and posts the reply there. Returns whether a handler matched
+    pub fn handle(&self, client: &Client, room: &str, message: &Message) -> Result<bool, Error> {
+        let reply = try!(self.dispatch(room, message));
+        send_reply(client, room, reply)
+    }
+    /// Dispatches the message of a `room_message` webhook event and posts the reply
+    pub fn handle_event(&self, client: &Client, event: &RoomMessageEvent) -> Result<bool, Error> {
+        let room_id = event.item.room.id.to_string();
+        let reply = try!(self.dispatch_in(&room_id, &[&room_id, &event.item.room.name], &event.item.message));
+        send_reply(client, &room_id, reply)
+    }
+    fn is_own_message(&self, message: &Message) -> bool {
+        match (self.mention_name.as_ref(), message.from.as_ref()) {
+            (Some(name), Some(from)) => from.mention_name.eq_ignore_ascii_case(name),
+            _ => false
+        }
+    }
+}
+
+fn send_reply(client: &Client, room: &str, reply: Option<Reply>) -> Result<bool, Error> {
+    match reply {
+        Some(Reply::Message(message)) => {
+            try!(client.send_message(room, message));
+        }
+        Some(Reply::Notification(notification)) => try!(client.send_notification(room, &notification)),
+        None => return Ok(false)
+    }
+    Ok(true)
+}
+
+/// Splits `/name args` into its name and trimmed arguments, as `MessageParser` does
+fn parse_command(text: &str) -> Option<(&str, &str)> {
+    match MessageParser::new().parse(text).first() {
+        Some(&Token::Command { name, args }) => Some((name, args)),
+        _ => None
+    }
+}
+
+/// Returns the text after a leading `@mention_name`, as `MessageParser` finds it
+fn strip_mention<'a>(text: &'a str, mention_name: &str) -> Option<&'a str> {
+    match MessageParser::new().parse(text).first() {
+        Some(&Token::Mention { name, .. }) if text.starts_with('@') && name.eq_ignore_ascii_case(mention_name) => {
+            let rest = &text[1 + name.len()..];
+            Some(rest.trim_start_matches([':', ',']).trim_start())
+        }
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::{self};
+
+    fn message(from: &str, text: &str) -> Message {
+        serde_json::from_value(json_message(from, text)).unwrap()
+    }
+
+    fn json_message(from: &str, text: &str) -> serde_json::Value {
+        serde_json::from_str(&format!(r#"{{
+            "id": "f1b4d2c8-6a47-4f0c-9ab0-5e5c2a8e9d77",
+            "date": "2017-03-20T12:00:00.000000+00:00",
+            "from": {{
+                "id": 1, "name": "{0}", "mention_name": "{0}",
+                "links": {{ "self": "https://api.hipchat.com/v2/user/1" }}
+            }},
+            "message": {1},
+            "type": "message",
+            "mentions": []
+        }}"#, from, serde_json::to_string(text).unwrap())).unwrap()
+    }
+
+    fn echo(ctx: &Context) -> Result<Option<Reply>, Error> {
+        Ok(Some(Reply::message(format!("{:?} {}", ctx.command, ctx.args))))
+    }
+
+    #[test]
+    fn unit_parse_command() {
+        assert_eq!(parse_command("/deploy api  prod "), Some(("deploy", "api  prod")));
+        assert_eq!(parse_command("/status"), Some(("status", "")));
+        assert_eq!(parse_command("/ deploy"), None);
+        assert_eq!(parse_command("deploy"), None);
+        assert_eq!(parse_command("/code fn main() {}"), None);
+    }
+
+    #[test]
+    fn unit_strip_mention() {
+        assert_eq!(strip_mention("@DeployBot: ship it", "deploybot"), Some("ship it"));
+        assert_eq!(strip_mention("@deploybot, ship  it\nto @deploybot", "deploybot"), Some("ship  it\nto @deploybot"));
+        assert_eq!(strip_mention("@deploybot", "deploybot"), Some(""));
+        assert_eq!(strip_mention("hey @deploybot, status?", "deploybot"), None);
+        assert_eq!(strip_mention("@deploybotter", "deploybot"), None);
+    }
+
+    #[test]
+    fn unit_dispatch_command() {
+        let mut bot = Bot::new();
+        bot.command("/deploy", echo);
+        let reply = bot.dispatch("Ops", &message("alice", "/Deploy api prod")).unwrap();
+        assert_eq!(reply, Some(Reply::message("Some(\"Deploy\") api prod")));
+        assert_eq!(bot.dispatch("Ops", &message("alice", "/status")).unwrap(), None);
+    }
+
+    #[test]
+    fn unit_dispatch_scopes_handlers_to_rooms() {
+        let mut bot = Bot::new();
+        bot.command_in("deploy", &["Ops"], |_: &Context| Ok(Some(Reply::message("ops"))))
+           .command("deploy", |_: &Context| Ok(Some(Reply::message("elsewhere"))));
+        assert_eq!(bot.dispatch("ops", &message("alice", "/deploy")).unwrap(), Some(Reply::message("ops")));
+        assert_eq!(bot.dispatch("Dev", &message("alice", "/deploy")).unwrap(), Some(Reply::message("elsewhere")));
+    }
+
+    #[test]
+    fn unit_dispatch_mention_ignores_own_messages() {
+        let mut bot = Bot::with_mention_name("@deploybot");
+        bot.mention(echo);
+        let reply = bot.dispatch("Ops", &message("alice", "@deploybot status please")).unwrap();
+        assert_eq!(reply, Some(Reply::message("None status please")));
+        assert_eq!(bot.dispatch("Ops", &message("deploybot", "@deploybot status please")).unwrap(), None);
+    }
+
+    #[test]
+    fn unit_deserialize_room_message_event() {
+        let mut event = serde_json::Map::new();
+        event.insert("event".to_owned(), "room_message".into());
+        event.insert("oauth_client_id".to_owned(), "a3d4c2d8".into());
+        event.insert("webhook_id".to_owned(), 7.into());
+        let mut item = serde_json::Map::new();
+        item.insert("message".to_owned(), json_message("alice", "/deploy"));
+        item.insert("room".to_owned(), serde_json::from_str(r#"{"id": 42, "name": "Ops"}"#).unwrap());
+        event.insert("item".to_owned(), serde_json::Value::Object(item));
+
+        let event: RoomMessageEvent = serde_json::from_value(serde_json::Value::Object(event)).unwrap();
+        assert_eq!(event.item.room, WebhookRoom { id: 42, name: "Ops".to_owned() });
+        assert_eq!(event.item.message.message, "/deploy");
+    }
+}
@@ -0,0 +1,314 @@
+//! An in-memory cache in front of the lookups bots make most often
+
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use hyper::Url;
+use hyper::status::StatusCode;
+
+use auth::Scope;
+use client::Client;
+use conditional::Conditional;
+use emoticon::Emoticon;
+use error::Error;
+use room::{RoomDetail, RoomUpdate, Rooms, RoomsRequest};
+use user::UserDetail;
+use util::AppendToQueryParams;
+
+const DEFAULT_TTL: u64 = 300;
+const DEFAULT_MAX_ENTRIES: usize = 1000;
+
+struct Entry<T> {
+    value: Arc<T>,
+    etag: Option<String>,
+    fetched: Instant,
+    last_used: Instant
+}
+
+/// Entries of one kind, evicting the least recently used beyond `max_entries`
+struct Store<T> {
+    entries: HashMap<String, Entry<T>>
+}
+
+impl<T> Store<T> {
+    fn new() -> Self {
+        Store { entries: HashMap::new() }
+    }
+    fn insert(&mut self, key: String, value: Arc<T>, etag: Option<String>, max_entries: usize) {
+        if max_entries == 0 {
+            return;
+        }
+        if !self.entries.contains_key(&key) && self.entries.len() >= max_entries {
+            let oldest = self.entries.iter()
+                .min_by_key(|&(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        let now = Instant::now();
+        self.entries.insert(key, Entry { value: value, etag: etag, fetched: now, last_used: now });
+    }
+}
+
+/// Wraps a `Client` and caches `get_user`, `get_room`, `get_emoticon` and `get_rooms`
+///
+/// Entries are served for `ttl` after they were fetched. Once stale they are
+/// fetched again, or with `revalidate` enabled, checked with `If-None-Match`
+/// so unchanged documents are not downloaded again. `update_room`,
+/// `patch_room`, `modify_room` and `delete_room` go through the cache and drop
+/// what they make stale; writes made through `client()` do not.
+/// `C` is anything that derefs to a `Client`, such as `&Client` or `Arc<Client>`.
+pub struct CachedClient<C: Deref<Target = Client>> {
+    client: C,
+    ttl: Duration,
+    max_entries: usize,
+    revalidate: bool,
+    users: Mutex<Store<UserDetail>>,
+    rooms: Mutex<Store<RoomDetail>>,
+    emoticons: Mutex<Store<Emoticon>>,
+    room_lists: Mutex<Store<Rooms>>
+}
+
+impl<C: Deref<Target = Client>> CachedClient<C> {
+    pub fn new(client: C) -> Self {
+        CachedClient {
+            client: client,
+            ttl: Duration::from_secs(DEFAULT_TTL),
+            max_entries: DEFAULT_MAX_ENTRIES,
+            revalidate: false,
+            users: Mutex::new(Store::new()),
+            rooms: Mutex::new(Store::new()),
+            emoticons: Mutex::new(Store::new()),
+            room_lists: Mutex::new(Store::new())
+        }
+    }
+    /// How long entries are served without asking HipChat. Defaults to 5 minutes
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+    /// Most entries kept of each kind (users, rooms, emoticons, room lists). Defaults to 1000, and 0 disables caching
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+    /// Revalidates stale entries with their ETag instead of fetching them again
+    pub fn revalidate(mut self, revalidate: bool) -> Self {
+        self.revalidate = revalidate;
+        self
+    }
+    /// The wrapped client, for calls that bypass the cache
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+    pub fn get_user<T: AsRef<str>>(&self, user_id_or_name: T) -> Result<Arc<UserDetail>, Error> {
+        let path = format!("user/{}", user_id_or_name.as_ref());
+        self.lookup(&self.users, Scope::ViewGroup, &path)
+    }
+    pub fn get_room<T: AsRef<str>>(&self, room_id_or_name: T) -> Result<Arc<RoomDetail>, Error> {
+        let path = format!("room/{}", room_id_or_name.as_ref());
+        self.lookup(&self.rooms, Scope::ViewRoom, &path)
+    }
+    pub fn get_emoticon<T: AsRef<str>>(&self, emoticon_id_or_shortcut: T) -> Result<Arc<Emoticon>, Error> {
+        let path = format!("emoticon/{}", emoticon_id_or_shortcut.as_ref());
+        self.lookup(&self.emoticons, Scope::ViewGroup, &path)
+    }
+    pub fn get_rooms(&self, req: Option<&RoomsRequest>) -> Result<Arc<Rooms>, Error> {
+        let mut url = Url::parse("https://example.com/").unwrap();
+        if let Some(rooms_request) = req {
+            rooms_request.append_to(&mut url.query_pairs_mut());
+        }
+        let key = url.query().unwrap_or("").to_owned();
+
+        if let Some(rooms) = self.fresh(&self.room_lists, &key) {
+            return Ok(rooms);
+        }
+        let rooms = Arc::new(try!(self.client.get_rooms(req)));
+        self.room_lists.lock().unwrap().insert(key, rooms.clone(), None, self.max_entries);
+        Ok(rooms)
+    }
+    /// Updates a room and drops it and all room lists from the cache
+    pub fn update_room<T: AsRef<str>>(&self, room_id_or_name: T, req: &RoomUpdate) -> Result<(), Error> {
+        let result = self.client.update_room(room_id_or_name.as_ref(), req);
+        self.invalidate_room(room_id_or_name.as_ref());
+        result
+    }
+    /// Patches a room with `Client::patch_room` and drops it and all room lists from the cache
+    pub fn patch_room<T: AsRef<str>>(&self, room_id_or_name: T, patch: &RoomUpdate) -> Result<(), Error> {
+        let result = self.client.patch_room(room_id_or_name.as_ref(), patch);
+        self.invalidate_room(room_id_or_name.as_ref());
+        result
+    }
+    /// Modifies a room with `Client::modify_room` and drops it and all room lists from the cache
+    pub fn modify_room<T: AsRef<str>, F: FnMut(&mut RoomDetail)>(&self, room_id_or_name: T, f: F) -> Result<(), Error> {
+        let result = self.client.modify_room(room_id_or_name.as_ref(), f);
+        self.invalidate_room(room_id_or_name.as_ref());
+        result
+    }
+    /// Deletes a room and drops it and all room lists from the cache
+    pub fn delete_room<T: AsRef<str>>(&self, room_id_or_name: T) -> Result<(), Error> {
+        let result = self.client.delete_room(room_id_or_name.as_ref());
+        self.invalidate_room(room_id_or_name.as_ref());
+        result
+    }
+    /// Drops a room, under any id or name it was looked up by, and all room lists
+    pub fn invalidate_room(&self, room_id_or_name: &str) {
+        self.rooms.lock().unwrap().entries.retain(|key, entry| {
+            let room = &entry.value;
+            key.as_str() != format!("room/{}", room_id_or_name) && room.id.to_string() != room_id_or_name && room.name != room_id_or_name
+        });
+        self.room_lists.lock().unwrap().entries.clear();
+    }
+    /// Drops a user, under any id, mention name or email it was looked up by
+    pub fn invalidate_user(&self, user_id_or_name: &str) {
+        self.users.lock().unwrap().entries.retain(|key, entry| {
+            let user = &entry.value;
+            key.as_str() != format!("user/{}", user_id_or_name) && user.id.to_string() != user_id_or_name &&
+                user.mention_name != user_id_or_name.trim_start_matches('@') && user.email.as_deref() != Some(user_id_or_name)
+        });
+    }
+    pub fn clear(&self) {
+        self.users.lock().unwrap().entries.clear();
+        self.rooms.lock().unwrap().entries.clear();
+        self.emoticons.lock().unwrap().entries.clear();
+        self.room_lists.lock().unwrap().entries.clear();
+    }
+    fn fresh<T>(&self, store: &Mutex<Store<T>>, key: &str) -> Option<Arc<T>> {
+        let mut store = store.lock().unwrap();
+        match store.entries.get_mut(key) {
+            Some(ref mut entry) if entry.fetched.elapsed() < self.ttl => {
+                entry.last_used = Instant::now();
+                Some(entry.value.clone())
+            }
+            _ => None
+        }
+    }
+    fn lookup<T: for<'de> ::serde::Deserialize<'de>>(&self, store: &Mutex<Store<T>>, scope: Scope, path: &str) -> Result<Arc<T>, Error> {
+        if let Some(value) = self.fresh(store, path) {
+            return Ok(value);
+        }
+        let stale = if self.revalidate {
+            store.lock().unwrap().entries.get(path).and_then(|entry| entry.etag.clone().map(|etag| (entry.value.clone(), etag)))
+        } else {
+            None
+        };
+        let etag = stale.as_ref().map(|stale| stale.1.as_str());
+
+        let (value, etag) = match (try!(self.client.get_conditional(scope, path, etag)), stale) {
+            (Conditional::Modified(value, meta), _) => (Arc::new(value), meta.etag),
+            (Conditional::NotModified(meta), Some((value, etag))) => (value, meta.etag.or(Some(etag))),
+            // A 304 to a request without If-None-Match leaves nothing to serve
+            (Conditional::NotModified(_), None) => return Err(Error::HttpStatus(StatusCode::NotModified))
+        };
+        store.lock().unwrap().insert(path.to_owned(), value.clone(), etag, self.max_entries);
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_server::{self, TestServer};
+
+    fn user(id: u64, mention_name: &str) -> String {
+        format!(r#"{{"id": {0}, "name": "{1}", "mention_name": "{1}", "email": "{1}@example.com",
+                    "links": {{"self": "https://api.hipchat.com/v2/user/{0}"}}}}"#, id, mention_name)
+    }
+
+    #[test]
+    fn unit_cached_client_serves_fresh_entries() {
+        let server = TestServer::new(vec![test_server::json(&user(1, "ada")), test_server::json(&user(1, "ada"))]);
+        let client = test_server::client(&server);
+        let cached = CachedClient::new(&client);
+
+        let first = cached.get_user("ada").unwrap();
+        let second = cached.get_user("ada").unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+
+        cached.invalidate_user("ada@example.com");
+        assert_eq!(cached.get_user("ada").unwrap().id, 1);
+        assert_eq!(server.requests.iter().take(2).count(), 2);
+    }
+
+    #[test]
+    fn unit_cached_client_revalidates_with_etag() {
+        let server = TestServer::new(vec![
+            test_server::response("200 OK", &[("Content-Type", "application/json"), ("ETag", "\"v1\"")], &user(1, "ada")),
+            test_server::response("304 Not Modified", &[("ETag", "\"v1\"")], "")
+        ]);
+        let client = test_server::client(&server);
+        let cached = CachedClient::new(&client).ttl(Duration::from_secs(0)).revalidate(true);
+
+        let first = cached.get_user("1").unwrap();
+        let second = cached.get_user("1").unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+
+        assert_eq!(server.requests.recv().unwrap().header("If-None-Match"), None);
+        assert_eq!(server.requests.recv().unwrap().header("If-None-Match"), Some("\"v1\""));
+    }
+
+    #[test]
+    fn unit_cached_client_evicts_least_recently_used() {
+        let server = TestServer::new(vec![
+            test_server::json(&user(1, "ada")),
+            test_server::json(&user(2, "grace")),
+            test_server::json(&user(3, "linus")),
+            test_server::json(&user(1, "ada"))
+        ]);
+        let client = test_server::client(&server);
+        let cached = CachedClient::new(&client).max_entries(2);
+
+        cached.get_user("1").unwrap();
+        cached.get_user("2").unwrap();
+        cached.get_user("2").unwrap();
+        cached.get_user("3").unwrap();
+        cached.get_user("1").unwrap();
+        let paths: Vec<String> = server.requests.iter().take(4).map(|request| request.path).collect();
+        assert_eq!(paths, vec!["/v2/user/1", "/v2/user/2", "/v2/user/3", "/v2/user/1"]);
+    }
+
+    #[test]
+    fn unit_cached_client_rejects_unrequested_not_modified() {
+        let server = TestServer::new(vec![test_server::response("304 Not Modified", &[], "")]);
+        let client = test_server::client(&server);
+        let cached = CachedClient::new(&client).revalidate(true);
+
+        match cached.get_user("1") {
+            Err(Error::HttpStatus(StatusCode::NotModified)) => (),
+            result => panic!("unexpected result {:?}", result.map(|user| user.id))
+        }
+    }
+
+    #[test]
+    fn unit_cached_client_without_entries_does_not_cache() {
+        let server = TestServer::new(vec![test_server::json(&user(1, "ada")), test_server::json(&user(1, "ada"))]);
+        let client = test_server::client(&server);
+        let cached = CachedClient::new(&client).max_entries(0);
+
+        cached.get_user("1").unwrap();
+        cached.get_user("1").unwrap();
+        assert_eq!(server.requests.iter().take(2).count(), 2);
+    }
+
+    #[test]
+    fn unit_cached_client_patch_room_invalidates() {
+        let room = test_server::room(7, "Ops", false);
+        let server = TestServer::new(vec![
+            test_server::json(&room),
+            test_server::json(&room),
+            test_server::response("204 No Content", &[], ""),
+            test_server::json(&room)
+        ]);
+        let client = test_server::client(&server);
+        let cached = CachedClient::new(&client);
+
+        cached.get_room("Ops").unwrap();
+        cached.patch_room("Ops", &RoomUpdate { topic: Some("new".to_owned()), ..RoomUpdate::default() }).unwrap();
+        cached.get_room("Ops").unwrap();
+        let methods: Vec<String> = server.requests.iter().take(4).map(|request| request.method).collect();
+        assert_eq!(methods, vec!["GET", "GET", "PUT", "GET"]);
+    }
+}
@@ -0,0 +1,159 @@
+//! The capabilities document a HipChat server publishes about itself
+//!
+//! `Client::capabilities` caches it and builds request URLs from the API
+//! root it advertises.
+
+use std::collections::BTreeMap;
+
+/// [Get capabilities](https://www.hipchat.com/docs/apiv2/method/get_capabilities)
+///
+/// Fields vary between HipChat versions, so everything past the name is optional.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Capabilities {
+    pub name: String,
+    pub key: Option<String>,
+    pub description: Option<String>,
+    pub api_version: Option<String>,
+    #[serde(default)]
+    pub links: CapabilitiesLinks,
+    #[serde(default)]
+    pub capabilities: ServerCapabilities
+}
+
+impl Capabilities {
+    /// Whether the server offers the named scope, e.g. `view_messages`
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.capabilities.hipchat_api_provider.as_ref()
+            .map(|provider| provider.available_scopes.contains_key(scope))
+            .unwrap_or(false)
+    }
+    /// The OAuth token endpoint, if the server advertises one
+    pub fn token_url(&self) -> Option<&str> {
+        self.capabilities.oauth2_provider.as_ref().map(|provider| provider.token_url.as_str())
+    }
+    /// The API root, without a trailing slash, if the server advertises one
+    pub fn api_url(&self) -> Option<&str> {
+        self.capabilities.hipchat_api_provider.as_ref().map(|provider| provider.url.trim_end_matches('/'))
+    }
+    /// The limits the server puts on Connect add-ons, if it advertises any
+    pub fn connect_limits(&self) -> Option<&ConnectLimits> {
+        self.capabilities.connect.as_ref().and_then(|connect| connect.limits.as_ref())
+    }
+}
+
+#[derive(Debug, Clone, Default, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CapabilitiesLinks {
+    #[serde(rename = "self")]
+    pub self_: Option<String>,
+    pub api: Option<String>,
+    pub homepage: Option<String>,
+    pub subdomain: Option<String>
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerCapabilities {
+    pub hipchat_api_provider: Option<ApiProvider>,
+    pub oauth2_provider: Option<OAuth2Provider>,
+    pub connect: Option<ConnectProvider>
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiProvider {
+    pub url: String,
+    #[serde(default)]
+    pub available_scopes: BTreeMap<String, AvailableScope>
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct AvailableScope {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OAuth2Provider {
+    pub authorization_url: String,
+    pub token_url: String
+}
+
+#[derive(Debug, Clone, Default, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectProvider {
+    #[serde(default)]
+    pub allowed_domains: Vec<String>,
+    pub limits: Option<ConnectLimits>
+}
+
+/// Limits on Connect add-ons. Servers leave out the ones they do not enforce
+#[derive(Debug, Clone, Default, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectLimits {
+    /// API requests allowed per installation in each `rate_limit_period`
+    pub rate_limit: Option<u64>,
+    /// Length of a rate limit period, in seconds
+    pub rate_limit_period: Option<u64>,
+    pub max_glances: Option<u64>,
+    pub max_webhooks: Option<u64>,
+    pub max_web_panels: Option<u64>
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::{self};
+
+    #[test]
+    fn unit_deserialize_capabilities() {
+        let capabilities: Capabilities = serde_json::from_str(r#"{
+            "name": "HipChat",
+            "key": "hipchat",
+            "description": "Group chat and IM built for teams",
+            "links": {
+                "self": "https://api.hipchat.com/v2/capabilities",
+                "api": "https://api.hipchat.com/v2",
+                "homepage": "https://www.hipchat.com"
+            },
+            "vendor": {"name": "Atlassian", "url": "http://atlassian.com"},
+            "capabilities": {
+                "hipchatApiProvider": {
+                    "url": "https://api.hipchat.com/v2/",
+                    "availableScopes": {
+                        "view_messages": {"id": "view_messages", "name": "View Messages", "description": "View messages from chat rooms"}
+                    }
+                },
+                "oauth2Provider": {
+                    "authorizationUrl": "https://www.hipchat.com/users/authorize",
+                    "tokenUrl": "https://api.hipchat.com/v2/oauth/token"
+                },
+                "connect": {
+                    "allowedDomains": ["example.com"],
+                    "limits": {"rateLimit": 500, "rateLimitPeriod": 300, "maxGlances": 10}
+                }
+            }
+        }"#).unwrap();
+        assert!(capabilities.has_scope("view_messages"));
+        assert!(!capabilities.has_scope("import_data"));
+        assert_eq!(capabilities.token_url(), Some("https://api.hipchat.com/v2/oauth/token"));
+        assert_eq!(capabilities.links.api, Some("https://api.hipchat.com/v2".to_owned()));
+        assert_eq!(capabilities.api_url(), Some("https://api.hipchat.com/v2"));
+        let limits = capabilities.connect_limits().unwrap();
+        assert_eq!(limits.rate_limit, Some(500));
+        assert_eq!(limits.rate_limit_period, Some(300));
+        assert_eq!(limits.max_glances, Some(10));
+        assert_eq!(limits.max_webhooks, None);
+    }
+
+    #[test]
+    fn unit_deserialize_minimal_capabilities() {
+        let capabilities: Capabilities = serde_json::from_str(r#"{"name": "HipChat Server"}"#).unwrap();
+        assert!(!capabilities.has_scope("view_messages"));
+        assert_eq!(capabilities.token_url(), None);
+        assert_eq!(capabilities.api_url(), None);
+        assert_eq!(capabilities.connect_limits(), None);
+    }
+}
@@ -1,32 +1,52 @@
 use std::io::prelude::*;
 use std::collections::BTreeMap;
+use std::sync::Mutex;
 use std::time::Duration;
 
 use hyper::Client as HyperClient;
+use hyper::client::{IntoUrl, ProxyConfig, Response};
+use hyper::error::Error as HyperError;
+use hyper::method::Method;
 use hyper::net::HttpsConnector;
-use hyper_native_tls::NativeTlsClient;
 use hyper::Url;
-use hyper::header::{Authorization, Bearer, ContentType};
-use hyper::status::StatusClass;
+use hyper::header::{Authorization, Bearer, ContentType, Header, HeaderFormat, Headers, UserAgent};
+use hyper::status::{StatusClass, StatusCode};
+use serde::Deserialize;
 use serde_json::{self};
 
+use auth::{CredentialProvider, OAuthCredentials, Scope, Session, StaticToken, TokenRequest};
+use capabilities::Capabilities;
+use conditional::{Conditional, ResponseMeta};
+use connector::TimeoutConnector;
 use emoticon::Emoticon;
-use error::Error;
-use room::{RoomDetail, RoomUpdate, Rooms, RoomsRequest, Notification};
-use user::{UserDetail, Users, UsersRequest};
-use message::{Messages, MessagesRequest, SendMessageResponse};
-use util::AppendToQueryParams;
+use error::{ApiError, Error};
+use glance::{GlanceUpdate, GlanceUpdates};
+use group::{Group, GroupStatistics};
+use room::{RoomDetail, RoomStatistics, RoomUpdate, Rooms, RoomsRequest, Notification, MESSAGE_MAX_LENGTH};
+use user::{UserDetail, UserInvite, UserInviteResponse, Users, UsersRequest};
+use message::{Messages, MessagesRequest, RecentHistoryRequest, SendMessageResponse};
+use tls::{self, RootCertificate};
+use util::{AppendToQueryParams, validate_length};
 
 const DEFAULT_TIMEOUT: u64 = 120;
+const DEFAULT_ORIGIN: &str = "https://api.hipchat.com";
+/// Reads and writes `modify_room` tries before giving up on a room that keeps changing
+pub const MODIFY_ROOM_MAX_ATTEMPTS: u32 = 3;
 
 pub struct Client {
     base_url: String,
-    auth: Authorization<Bearer>,
-    hyper_client: HyperClient
+    credentials: Box<CredentialProvider>,
+    scopes: Option<Vec<Scope>>,
+    /// Behind a mutex because `Headers` is not `Sync`, which `Arc<Client>` needs to be shared
+    headers: Mutex<Headers>,
+    hyper_client: HyperClient,
+    capabilities: Mutex<Option<Capabilities>>
 }
 
 impl Client {
     /// Creates a new HipChat API v2 client
+    ///
+    /// Panics if the TLS backend cannot be initialized; use `ClientBuilder` to get that as an `Error`.
     pub fn new<T: Into<String>, O: AsRef<str>>(origin: O, token: T) -> Self {
         let duration = Duration::new(DEFAULT_TIMEOUT, 0);
         Client::with_timeouts(origin, token, duration)
@@ -35,29 +55,105 @@ impl Client {
     pub fn with_hyper_client<T: Into<String>, O: AsRef<str>>(origin: O, token: T, hyper_client: HyperClient) -> Self {
         Client {
             base_url: format!("{}/v2", origin.as_ref()),
-            auth: Authorization(Bearer { token: token.into() }),
-            hyper_client: hyper_client
+            credentials: Box::new(StaticToken(token.into())),
+            scopes: None,
+            headers: Mutex::new(Headers::new()),
+            hyper_client: hyper_client,
+            capabilities: Mutex::new(None)
         }
     }
     /// Creates a new HipChat API v2 client that has read and write timeouts
+    ///
+    /// The origin is used as given, without validation. Panics if the TLS
+    /// backend cannot be initialized; use `ClientBuilder` to get that as an `Error`.
     pub fn with_timeouts<T: Into<String>, O: AsRef<str>>(origin: O, token: T, duration: Duration) -> Self {
-        let ssl = NativeTlsClient::new().unwrap();
-        let connector = HttpsConnector::new(ssl);
+        let ssl = tls::tls_client(&[]).unwrap();
+        let connector = HttpsConnector::with_connector(ssl, TimeoutConnector::default());
         let mut hyper_client = HyperClient::with_connector(connector);
         hyper_client.set_read_timeout(Some(duration));
         hyper_client.set_write_timeout(Some(duration));
+        Client::with_hyper_client(origin, token, hyper_client)
+    }
+    /// Creates a `ClientBuilder` for configuring a client
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
+    /// The scopes granted to this client's token, if known
+    pub fn scopes(&self) -> Option<&[Scope]> {
+        self.scopes.as_deref()
+    }
+    /// Looks up the scopes granted to this client's token so that each method
+    /// checks for its required scope before making a request
+    pub fn load_scopes(&mut self) -> Result<Session, Error> {
+        let token = try!(self.credentials.token());
+        let session = try!(self.get_session(token));
+        self.scopes = Some(session.scopes());
+        Ok(session)
+    }
+    /// Fails with `Error::MissingScope` for the first of `scopes` that is not granted to this client's token
+    pub fn require_scopes(&self, scopes: &[Scope]) -> Result<(), Error> {
+        for &scope in scopes {
+            try!(self.require_scope(scope));
+        }
+        Ok(())
+    }
+    fn require_scope(&self, scope: Scope) -> Result<(), Error> {
+        match self.scopes {
+            Some(ref scopes) if !scopes.contains(&scope) => Err(Error::MissingScope(scope)),
+            _ => Ok(())
+        }
+    }
+    /// Sends an authenticated request, retrying once with a new token if the current one is rejected
+    fn send<U: IntoUrl>(&self, method: Method, url: U, body: Option<&str>) -> Result<Response, Error> {
+        self.send_with_headers(method, url, body, &Headers::new())
+    }
+    fn send_with_headers<U: IntoUrl>(&self, method: Method, url: U, body: Option<&str>, extra_headers: &Headers) -> Result<Response, Error> {
+        let url = try!(url.into_url().map_err(HyperError::from));
+        let mut retried = false;
+        loop {
+            let mut headers = self.headers.lock().unwrap().clone();
+            headers.extend(extra_headers.iter());
+            headers.set(Authorization(Bearer { token: try!(self.credentials.token()) }));
+            let mut req = self.hyper_client.request(method.clone(), url.clone());
+            if let Some(body) = body {
+                headers.set(ContentType::json());
+                req = req.body(body.as_bytes());
+            }
 
-        Client {
-            base_url: format!("{}/v2", origin.as_ref()),
-            auth: Authorization(Bearer { token: token.into() }),
-            hyper_client: hyper_client
+            let res = try!(req.headers(headers).send());
+            if res.status == StatusCode::Unauthorized && !retried && self.credentials.invalidate() {
+                retried = true;
+                continue;
+            }
+            return Ok(res);
         }
     }
+    /// GETs `path`, relative to the API root, sending `If-None-Match` when an ETag is given
+    pub(crate) fn get_conditional<T: for<'de> Deserialize<'de>>(&self, scope: Scope, path: &str, if_none_match: Option<&str>) -> Result<Conditional<T>, Error> {
+        try!(self.require_scope(scope));
+        let mut headers = Headers::new();
+        if let Some(etag) = if_none_match {
+            headers.set_raw("If-None-Match", vec![etag.as_bytes().to_vec()]);
+        }
+        let mut res = try!(self.send_with_headers(Method::Get, &format!("{}/{}", self.api_base(), path), None, &headers));
+
+        let meta = ResponseMeta::from_response(&res);
+        if res.status == StatusCode::NotModified {
+            return Ok(Conditional::NotModified(meta));
+        }
+        if res.status.class() != StatusClass::Success {
+            return Err(Error::HttpStatus(res.status));
+        }
+
+        let mut body = String::new();
+        try!(res.read_to_string(&mut body));
+        let document: T = try!(serde_json::from_str(&body));
+        Ok(Conditional::Modified(document, meta))
+    }
     /// [Get emoticon](https://www.hipchat.com/docs/apiv2/method/get_emoticon)
     pub fn get_emoticon<T: AsRef<str>>(&self, emoticon_id_or_shortcut: T) -> Result<Emoticon, Error> {
-        let mut res = try!(self.hyper_client.get(&format!("{}/emoticon/{}", self.base_url, emoticon_id_or_shortcut.as_ref()))
-            .header(self.auth.to_owned())
-            .send());
+        try!(self.require_scope(Scope::ViewGroup));
+        let mut res = try!(self.send(Method::Get, &format!("{}/emoticon/{}", self.api_base(), emoticon_id_or_shortcut.as_ref()), None));
 
         if res.status.class() != StatusClass::Success {
             return Err(Error::HttpStatus(res.status));
@@ -70,9 +166,8 @@ impl Client {
     }
     /// [Get room](https://www.hipchat.com/docs/apiv2/method/get_room)
     pub fn get_room<T: AsRef<str>>(&self, room_id_or_name: T) -> Result<RoomDetail, Error> {
-        let mut res = try!(self.hyper_client.get(&format!("{}/room/{}", self.base_url, room_id_or_name.as_ref()))
-            .header(self.auth.to_owned())
-            .send());
+        try!(self.require_scope(Scope::ViewRoom));
+        let mut res = try!(self.send(Method::Get, &format!("{}/room/{}", self.api_base(), room_id_or_name.as_ref()), None));
 
         if res.status.class() != StatusClass::Success {
             return Err(Error::HttpStatus(res.status));
@@ -83,14 +178,84 @@ impl Client {
         let room: RoomDetail = try!(serde_json::from_str(&body));
         Ok(room)
     }
+    /// [Get room](https://www.hipchat.com/docs/apiv2/method/get_room), with the ETag and Last-Modified headers
+    ///
+    /// Pass the ETag from an earlier response to get `Conditional::NotModified` if the room has not changed.
+    pub fn get_room_conditional<T: AsRef<str>>(&self, room_id_or_name: T, if_none_match: Option<&str>) -> Result<Conditional<RoomDetail>, Error> {
+        self.get_conditional(Scope::ViewRoom, &format!("room/{}", room_id_or_name.as_ref()), if_none_match)
+    }
+    /// [Get room statistics](https://www.hipchat.com/docs/apiv2/method/get_room_statistics)
+    pub fn get_room_statistics<T: AsRef<str>>(&self, room_id_or_name: T) -> Result<RoomStatistics, Error> {
+        try!(self.require_scope(Scope::ViewRoom));
+        let mut res = try!(self.send(Method::Get, &format!("{}/room/{}/statistics", self.api_base(), room_id_or_name.as_ref()), None));
+
+        if res.status.class() != StatusClass::Success {
+            return Err(Error::HttpStatus(res.status));
+        }
+
+        let mut body = String::new();
+        try!(res.read_to_string(&mut body));
+        let statistics: RoomStatistics = try!(serde_json::from_str(&body));
+        Ok(statistics)
+    }
     /// [Update room](https://www.hipchat.com/docs/apiv2/method/update_room)
     pub fn update_room<T: AsRef<str>>(&self, room_id_or_name: T, req: &RoomUpdate) -> Result<(), Error> {
+        self.put_room(room_id_or_name.as_ref(), req, None)
+    }
+    /// [Update room](https://www.hipchat.com/docs/apiv2/method/update_room) only if it still has the given ETag
+    ///
+    /// Fails with `Error::HttpStatus(StatusCode::PreconditionFailed)` if the room changed since `etag` was read.
+    pub fn update_room_if_match<T: AsRef<str>>(&self, room_id_or_name: T, etag: &str, req: &RoomUpdate) -> Result<(), Error> {
+        self.put_room(room_id_or_name.as_ref(), req, Some(etag))
+    }
+    /// Reads a room, applies `f` to it and writes it back
+    ///
+    /// The write is conditional on the ETag of the read, so a concurrent change
+    /// makes it fail; the room is then read again and `f` applied afresh, up to
+    /// `MODIFY_ROOM_MAX_ATTEMPTS` times. Servers that send no ETag get a plain update.
+    /// Rooms without an owner fail with `Error::Validation`, as the update requires one.
+    pub fn modify_room<T: AsRef<str>, F: FnMut(&mut RoomDetail)>(&self, room_id_or_name: T, mut f: F) -> Result<(), Error> {
+        self.read_modify_write_room(room_id_or_name.as_ref(), |room| {
+            f(room);
+            RoomUpdate::from_room(room)
+        })
+    }
+    /// Updates only the fields set in `patch`, keeping the rest of the room as it is
+    ///
+    /// HipChat's update replaces every field, so the room is read first and
+    /// written back with `patch` merged in, retried like `modify_room`.
+    pub fn patch_room<T: AsRef<str>>(&self, room_id_or_name: T, patch: &RoomUpdate) -> Result<(), Error> {
+        try!(patch.validate());
+        self.read_modify_write_room(room_id_or_name.as_ref(), |room| RoomUpdate::from_patched_room(room, patch))
+    }
+    fn read_modify_write_room<F: FnMut(&mut RoomDetail) -> Result<RoomUpdate, Error>>(&self, room_id_or_name: &str, mut f: F) -> Result<(), Error> {
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let (mut room, meta) = match try!(self.get_room_conditional(room_id_or_name, None)) {
+                Conditional::Modified(room, meta) => (room, meta),
+                Conditional::NotModified(_) => return Err(Error::HttpStatus(StatusCode::NotModified))
+            };
+            let update = try!(f(&mut room));
+            let result = match meta.etag {
+                Some(ref etag) => self.update_room_if_match(room.id.to_string(), etag, &update),
+                None => self.update_room(room.id.to_string(), &update)
+            };
+            match result {
+                Err(Error::HttpStatus(StatusCode::PreconditionFailed)) if attempts < MODIFY_ROOM_MAX_ATTEMPTS => continue,
+                result => return result
+            }
+        }
+    }
+    fn put_room(&self, room_id_or_name: &str, req: &RoomUpdate, if_match: Option<&str>) -> Result<(), Error> {
+        try!(self.require_scope(Scope::AdminRoom));
+        try!(req.validate());
         let body = serde_json::to_string(req).unwrap();
-        let res = try!(self.hyper_client.put(&format!("{}/room/{}", self.base_url, room_id_or_name.as_ref()))
-            .header(self.auth.to_owned())
-            .header(ContentType::json())
-            .body(body.as_bytes())
-            .send());
+        let mut headers = Headers::new();
+        if let Some(etag) = if_match {
+            headers.set_raw("If-Match", vec![etag.as_bytes().to_vec()]);
+        }
+        let res = try!(self.send_with_headers(Method::Put, &format!("{}/room/{}", self.api_base(), room_id_or_name), Some(&body), &headers));
 
         if res.status.class() != StatusClass::Success {
             return Err(Error::HttpStatus(res.status));
@@ -100,9 +265,8 @@ impl Client {
     }
     /// [Delete room](https://www.hipchat.com/docs/apiv2/method/delete_room)
     pub fn delete_room<T: AsRef<str>>(&self, room_id_or_name: T) -> Result<(), Error> {
-        let res = try!(self.hyper_client.delete(&format!("{}/room/{}", self.base_url, room_id_or_name.as_ref()))
-            .header(self.auth.to_owned())
-            .send());
+        try!(self.require_scope(Scope::ManageRooms));
+        let res = try!(self.send(Method::Delete, &format!("{}/room/{}", self.api_base(), room_id_or_name.as_ref()), None));
 
         if res.status.class() != StatusClass::Success {
             return Err(Error::HttpStatus(res.status));
@@ -111,12 +275,14 @@ impl Client {
     }
     /// [Get all rooms](https://www.hipchat.com/docs/apiv2/method/get_all_rooms)
     pub fn get_rooms(&self, req: Option<&RoomsRequest>) -> Result<Rooms, Error> {
-        let mut url = Url::parse(&format!("{}/room", self.base_url)).unwrap();
+        try!(self.require_scope(Scope::ViewGroup));
+        if let Some(rooms_request) = req {
+            try!(rooms_request.validate());
+        }
+        let mut url = Url::parse(&format!("{}/room", self.api_base())).unwrap();
         req.map(|rooms_request| rooms_request.append_to(&mut url.query_pairs_mut()));
 
-        let mut res = try!(self.hyper_client.get(url)
-            .header(self.auth.to_owned())
-            .send());
+        let mut res = try!(self.send(Method::Get, url, None));
 
         if res.status.class() != StatusClass::Success {
             return Err(Error::HttpStatus(res.status));
@@ -129,15 +295,14 @@ impl Client {
     }
     /// [Send message](https://www.hipchat.com/docs/apiv2/method/send_message)
     pub fn send_message<T: AsRef<str>, U: Into<String>>(&self, room_id_or_name: T, message: U) -> Result<SendMessageResponse, Error> {
+        try!(self.require_scope(Scope::SendMessage));
+        let message = message.into();
+        try!(validate_length("message", &message, 1, MESSAGE_MAX_LENGTH));
         let mut obj = BTreeMap::new();
-        obj.insert("message".to_owned(), message.into());
+        obj.insert("message".to_owned(), message);
         let body = serde_json::to_string(&obj).unwrap();
 
-        let mut res = try!(self.hyper_client.post(&format!("{}/room/{}/message", self.base_url, room_id_or_name.as_ref()))
-            .header(self.auth.to_owned())
-            .header(ContentType::json())
-            .body(body.as_bytes())
-            .send());
+        let mut res = try!(self.send(Method::Post, &format!("{}/room/{}/message", self.api_base(), room_id_or_name.as_ref()), Some(&body)));
 
         if res.status.class() != StatusClass::Success {
             return Err(Error::HttpStatus(res.status));
@@ -150,12 +315,14 @@ impl Client {
     }
     /// [Get Private Messages](https://www.hipchat.com/docs/apiv2/method/view_privatechat_history)
     pub fn get_private_messages<T: AsRef<str>>(&self, user_id_or_email: T, req: Option<&MessagesRequest>) -> Result<Messages, Error> {
-        let mut url = Url::parse(&format!("{}/user/{}/history", self.base_url, user_id_or_email.as_ref())).unwrap();
+        try!(self.require_scope(Scope::ViewMessages));
+        if let Some(messages_request) = req {
+            try!(messages_request.validate());
+        }
+        let mut url = Url::parse(&format!("{}/user/{}/history", self.api_base(), user_id_or_email.as_ref())).unwrap();
         req.map(|messages_request| messages_request.append_to(&mut url.query_pairs_mut()));
 
-        let mut res = try!(self.hyper_client.get(url)
-            .header(self.auth.to_owned())
-            .send());
+        let mut res = try!(self.send(Method::Get, url, None));
 
         if res.status.class() != StatusClass::Success {
             return Err(Error::HttpStatus(res.status));
@@ -167,13 +334,62 @@ impl Client {
         Ok(private_message)
     }
 
+    /// [View room history](https://www.hipchat.com/docs/apiv2/method/view_room_history)
+    pub fn get_room_history<T: AsRef<str>>(&self, room_id_or_name: T, req: Option<&MessagesRequest>) -> Result<Messages, Error> {
+        try!(self.require_scope(Scope::ViewMessages));
+        if let Some(messages_request) = req {
+            try!(messages_request.validate());
+        }
+        let mut url = Url::parse(&format!("{}/room/{}/history", self.api_base(), room_id_or_name.as_ref())).unwrap();
+        if let Some(messages_request) = req {
+            messages_request.append_to(&mut url.query_pairs_mut());
+        }
+
+        let mut res = try!(self.send(Method::Get, url, None));
+
+        if res.status.class() != StatusClass::Success {
+            return Err(Error::HttpStatus(res.status));
+        }
+
+        let mut body = String::new();
+        try!(res.read_to_string(&mut body));
+        let messages: Messages = try!(serde_json::from_str(&body));
+        Ok(messages)
+    }
+    /// Downloads a file shared in a room, such as `MessageFile.url`, and returns its size
+    ///
+    /// The token is only sent to URLs on the client's origin; files stored
+    /// elsewhere are fetched without credentials.
+    pub fn download_file<W: Write>(&self, url: &str, out: &mut W) -> Result<u64, Error> {
+        let mut res = if same_origin(url, &self.base_url) {
+            try!(self.send(Method::Get, url, None))
+        } else {
+            let url = try!(url.into_url().map_err(HyperError::from));
+            try!(self.hyper_client.get(url).headers(self.headers.lock().unwrap().clone()).send())
+        };
+
+        if res.status.class() != StatusClass::Success {
+            return Err(Error::HttpStatus(res.status));
+        }
+        Ok(try!(::std::io::copy(&mut res, out)))
+    }
+
     /// [View recent room history](https://www.hipchat.com/docs/apiv2/method/view_recent_room_history)
     pub fn get_recent_history<T: AsRef<str>>(&self, room_id_or_name: T) -> Result<Messages, Error> {
-        let url = Url::parse(&format!("{}/room/{}/history/latest", self.base_url, room_id_or_name.as_ref())).unwrap();
+        self.get_recent_history_with(room_id_or_name, None)
+    }
+    /// [View recent room history](https://www.hipchat.com/docs/apiv2/method/view_recent_room_history) with query parameters
+    pub fn get_recent_history_with<T: AsRef<str>>(&self, room_id_or_name: T, req: Option<&RecentHistoryRequest>) -> Result<Messages, Error> {
+        try!(self.require_scope(Scope::ViewMessages));
+        if let Some(recent_history_request) = req {
+            try!(recent_history_request.validate());
+        }
+        let mut url = Url::parse(&format!("{}/room/{}/history/latest", self.api_base(), room_id_or_name.as_ref())).unwrap();
+        if let Some(recent_history_request) = req {
+            recent_history_request.append_to(&mut url.query_pairs_mut());
+        }
 
-        let mut res = try!(self.hyper_client.get(url)
-            .header(self.auth.to_owned())
-            .send());
+        let mut res = try!(self.send(Method::Get, url, None));
 
         if res.status.class() != StatusClass::Success {
             return Err(Error::HttpStatus(res.status));
@@ -187,12 +403,10 @@ impl Client {
 
     /// [Send room notification](https://www.hipchat.com/docs/apiv2/method/send_room_notification)
     pub fn send_notification<T: AsRef<str>>(&self, room_id_or_name: T, notification: &Notification) -> Result<(), Error> {
+        try!(self.require_scope(Scope::SendNotification));
+        try!(notification.validate());
         let body = serde_json::to_string(notification).unwrap();
-        let res = try!(self.hyper_client.post(&format!("{}/room/{}/notification", self.base_url, room_id_or_name.as_ref()))
-            .header(self.auth.to_owned())
-            .header(ContentType::json())
-            .body(body.as_bytes())
-            .send());
+        let res = try!(self.send(Method::Post, &format!("{}/room/{}/notification", self.api_base(), room_id_or_name.as_ref()), Some(&body)));
 
         if res.status.class() != StatusClass::Success {
             return Err(Error::HttpStatus(res.status));
@@ -201,12 +415,14 @@ impl Client {
     }
     /// [Get all users](https://www.hipchat.com/docs/apiv2/method/get_all_users)
     pub fn get_users(&self, req: Option<&UsersRequest>) -> Result<Users, Error> {
-        let mut url = Url::parse(&format!("{}/user", self.base_url)).unwrap();
+        try!(self.require_scope(Scope::ViewGroup));
+        if let Some(users_request) = req {
+            try!(users_request.validate());
+        }
+        let mut url = Url::parse(&format!("{}/user", self.api_base())).unwrap();
         req.map(|users_request| users_request.append_to(&mut url.query_pairs_mut()));
 
-        let mut res = try!(self.hyper_client.get(url)
-            .header(self.auth.to_owned())
-            .send());
+        let mut res = try!(self.send(Method::Get, url, None));
 
         if res.status.class() != StatusClass::Success {
             return Err(Error::HttpStatus(res.status));
@@ -219,9 +435,8 @@ impl Client {
     }
     /// [Get user](https://www.hipchat.com/docs/apiv2/method/view_user)
     pub fn get_user<T: AsRef<str>>(&self, user_id_or_name: T) -> Result<UserDetail, Error> {
-        let mut res = try!(self.hyper_client.get(&format!("{}/user/{}", self.base_url, user_id_or_name.as_ref()))
-            .header(self.auth.to_owned())
-            .send());
+        try!(self.require_scope(Scope::ViewGroup));
+        let mut res = try!(self.send(Method::Get, &format!("{}/user/{}", self.api_base(), user_id_or_name.as_ref()), None));
 
         if res.status.class() != StatusClass::Success {
             return Err(Error::HttpStatus(res.status));
@@ -232,4 +447,640 @@ impl Client {
         let user_detail: UserDetail = try!(serde_json::from_str(&body));
         Ok(user_detail)
     }
+    /// [Get user](https://www.hipchat.com/docs/apiv2/method/view_user), with the ETag and Last-Modified headers
+    ///
+    /// Pass the ETag from an earlier response to get `Conditional::NotModified` if the user has not changed.
+    pub fn get_user_conditional<T: AsRef<str>>(&self, user_id_or_name: T, if_none_match: Option<&str>) -> Result<Conditional<UserDetail>, Error> {
+        self.get_conditional(Scope::ViewGroup, &format!("user/{}", user_id_or_name.as_ref()), if_none_match)
+    }
+    /// [Get session](https://www.hipchat.com/docs/apiv2/method/get_session)
+    pub fn get_session<T: AsRef<str>>(&self, access_token: T) -> Result<Session, Error> {
+        let mut res = try!(self.send(Method::Get, &format!("{}/oauth/token/{}", self.api_base(), access_token.as_ref()), None));
+
+        if res.status.class() != StatusClass::Success {
+            return Err(Error::HttpStatus(res.status));
+        }
+
+        let mut body = String::new();
+        try!(res.read_to_string(&mut body));
+        let session: Session = try!(serde_json::from_str(&body));
+        Ok(session)
+    }
+    /// [Invite user](https://www.hipchat.com/docs/apiv2/method/invite_user) to the group
+    ///
+    /// Fails with `Error::Api` when HipChat rejects the invitation, e.g. because the user is already a member.
+    pub fn invite_user_to_group(&self, email: &str, name: &str, title: Option<&str>) -> Result<UserInviteResponse, Error> {
+        try!(self.require_scope(Scope::AdminGroup));
+        let invite = UserInvite {
+            email: email.to_owned(),
+            name: name.to_owned(),
+            title: title.map(|title| title.to_owned())
+        };
+        let body = serde_json::to_string(&invite).unwrap();
+        let mut res = try!(self.send(Method::Post, &format!("{}/invite/user", self.api_base()), Some(&body)));
+
+        if res.status.class() != StatusClass::Success {
+            return Err(api_error(res));
+        }
+
+        let mut body = String::new();
+        try!(res.read_to_string(&mut body));
+        let response: UserInviteResponse = try!(serde_json::from_str(&body));
+        Ok(response)
+    }
+    /// [Invite user to room](https://www.hipchat.com/docs/apiv2/method/invite_user)
+    ///
+    /// Fails with `Error::Api` when HipChat rejects the invitation, e.g. because the user is already a member.
+    pub fn invite_user_to_room<T: AsRef<str>, U: AsRef<str>>(&self, room_id_or_name: T, user_id_or_email: U, reason: &str) -> Result<(), Error> {
+        try!(self.require_scope(Scope::AdminRoom));
+        let mut obj = BTreeMap::new();
+        obj.insert("reason".to_owned(), reason.to_owned());
+        let body = serde_json::to_string(&obj).unwrap();
+        let url = format!("{}/room/{}/invite/{}", self.api_base(), room_id_or_name.as_ref(), user_id_or_email.as_ref());
+        let res = try!(self.send(Method::Post, &url, Some(&body)));
+
+        if res.status.class() != StatusClass::Success {
+            return Err(api_error(res));
+        }
+        Ok(())
+    }
+    /// [Get group](https://www.hipchat.com/docs/apiv2/method/get_group)
+    pub fn get_group(&self, group_id: u64) -> Result<Group, Error> {
+        try!(self.require_scope(Scope::ViewGroup));
+        let mut res = try!(self.send(Method::Get, &format!("{}/group/{}", self.api_base(), group_id), None));
+
+        if res.status.class() != StatusClass::Success {
+            return Err(Error::HttpStatus(res.status));
+        }
+
+        let mut body = String::new();
+        try!(res.read_to_string(&mut body));
+        let group: Group = try!(serde_json::from_str(&body));
+        Ok(group)
+    }
+    /// [Get group statistics](https://www.hipchat.com/docs/apiv2/method/get_group_statistics)
+    pub fn get_group_statistics(&self, group_id: u64) -> Result<GroupStatistics, Error> {
+        try!(self.require_scope(Scope::ViewGroup));
+        let mut res = try!(self.send(Method::Get, &format!("{}/group/{}/statistics", self.api_base(), group_id), None));
+
+        if res.status.class() != StatusClass::Success {
+            return Err(Error::HttpStatus(res.status));
+        }
+
+        let mut body = String::new();
+        try!(res.read_to_string(&mut body));
+        let statistics: GroupStatistics = try!(serde_json::from_str(&body));
+        Ok(statistics)
+    }
+    /// [View group history](https://www.hipchat.com/docs/apiv2/method/view_group_history)
+    pub fn view_group_history(&self, group_id: u64, req: Option<&MessagesRequest>) -> Result<Messages, Error> {
+        try!(self.require_scope(Scope::ViewMessages));
+        if let Some(messages_request) = req {
+            try!(messages_request.validate());
+        }
+        let mut url = Url::parse(&format!("{}/group/{}/history", self.api_base(), group_id)).unwrap();
+        if let Some(messages_request) = req {
+            messages_request.append_to(&mut url.query_pairs_mut());
+        }
+
+        let mut res = try!(self.send(Method::Get, url, None));
+
+        if res.status.class() != StatusClass::Success {
+            return Err(Error::HttpStatus(res.status));
+        }
+
+        let mut body = String::new();
+        try!(res.read_to_string(&mut body));
+        let messages: Messages = try!(serde_json::from_str(&body));
+        Ok(messages)
+    }
+    /// [Get capabilities](https://www.hipchat.com/docs/apiv2/method/get_capabilities)
+    ///
+    /// Always makes a request, and replaces the document cached by `capabilities`.
+    pub fn get_capabilities(&self) -> Result<Capabilities, Error> {
+        let mut res = try!(self.send(Method::Get, &format!("{}/capabilities", self.base_url), None));
+
+        if res.status.class() != StatusClass::Success {
+            return Err(Error::HttpStatus(res.status));
+        }
+
+        let mut body = String::new();
+        try!(res.read_to_string(&mut body));
+        let capabilities: Capabilities = try!(serde_json::from_str(&body));
+        *self.capabilities.lock().unwrap() = Some(capabilities.clone());
+        Ok(capabilities)
+    }
+    /// The server's capabilities, fetched on first use and cached for the life of the client
+    ///
+    /// Once cached, requests go to the API root the server advertises, as long
+    /// as it is on the client's origin.
+    pub fn capabilities(&self) -> Result<Capabilities, Error> {
+        if let Some(ref capabilities) = *self.capabilities.lock().unwrap() {
+            return Ok(capabilities.clone());
+        }
+        self.get_capabilities()
+    }
+    /// The API root from the cached capabilities, falling back to `base_url`
+    fn api_base(&self) -> String {
+        let capabilities = self.capabilities.lock().unwrap();
+        match capabilities.as_ref().and_then(|capabilities| capabilities.api_url()) {
+            Some(api_url) if same_origin(api_url, &self.base_url) => api_url.to_owned(),
+            _ => self.base_url.clone()
+        }
+    }
+    /// [Update room glance](https://www.hipchat.com/docs/apiv2/method/room_addon_ui_update)
+    pub fn update_room_glances<T: AsRef<str>>(&self, room_id: T, glances: &[GlanceUpdate]) -> Result<(), Error> {
+        self.update_glances(&format!("{}/addon/ui/room/{}", self.api_base(), room_id.as_ref()), glances)
+    }
+    /// [Update user glance](https://www.hipchat.com/docs/apiv2/method/user_addon_ui_update)
+    pub fn update_user_glances<T: AsRef<str>>(&self, user_id: T, glances: &[GlanceUpdate]) -> Result<(), Error> {
+        self.update_glances(&format!("{}/addon/ui/user/{}", self.api_base(), user_id.as_ref()), glances)
+    }
+    fn update_glances(&self, url: &str, glances: &[GlanceUpdate]) -> Result<(), Error> {
+        for glance in glances {
+            try!(glance.validate());
+        }
+        let body = serde_json::to_string(&GlanceUpdates { glance: glances }).unwrap();
+        let res = try!(self.send(Method::Post, url, Some(&body)));
+
+        if res.status.class() != StatusClass::Success {
+            return Err(Error::HttpStatus(res.status));
+        }
+        Ok(())
+    }
+}
+
+/// Whether two URLs share scheme, host and port
+fn same_origin(a: &str, b: &str) -> bool {
+    match (Url::parse(a), Url::parse(b)) {
+        (Ok(a), Ok(b)) => a.origin() == b.origin(),
+        _ => false
+    }
+}
+
+/// `Error::Api` if the response carries a HipChat error body, `Error::HttpStatus` otherwise
+fn api_error(mut res: Response) -> Error {
+    let mut body = String::new();
+    if res.read_to_string(&mut body).is_ok() {
+        if let Some(e) = ApiError::parse(res.status, &body) {
+            return Error::Api(e);
+        }
+    }
+    Error::HttpStatus(res.status)
+}
+
+/// Configures and creates a `Client`
+///
+/// Unlike `Client::new`, building a client never panics. Any TLS or
+/// configuration problem is returned as an `Error` from `build`.
+pub struct ClientBuilder {
+    origin: String,
+    credentials: Option<Credentials>,
+    scopes: Option<Vec<Scope>>,
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    proxy: Option<(String, u16)>,
+    root_certificates: Vec<RootCertificate>,
+    headers: Headers
+}
+
+enum Credentials {
+    Provider(Box<CredentialProvider>),
+    /// Client id, client secret and token request for `OAuthCredentials` built by `ClientBuilder::build`
+    OAuth(String, String, TokenRequest)
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        ClientBuilder {
+            origin: DEFAULT_ORIGIN.to_owned(),
+            credentials: None,
+            scopes: None,
+            connect_timeout: None,
+            read_timeout: Some(Duration::new(DEFAULT_TIMEOUT, 0)),
+            write_timeout: Some(Duration::new(DEFAULT_TIMEOUT, 0)),
+            proxy: None,
+            root_certificates: Vec::new(),
+            headers: Headers::new()
+        }
+    }
+}
+
+impl ClientBuilder {
+    pub fn new() -> Self {
+        ClientBuilder::default()
+    }
+    /// Sets the HipChat origin, e.g. `https://hipchat.example.com`. Defaults to `https://api.hipchat.com`
+    pub fn origin<T: Into<String>>(mut self, origin: T) -> Self {
+        self.origin = origin.into();
+        self
+    }
+    pub fn token<T: Into<String>>(mut self, token: T) -> Self {
+        self.credentials = Some(Credentials::Provider(Box::new(StaticToken(token.into()))));
+        self
+    }
+    /// Authenticates with tokens from `credentials` instead of a fixed token
+    pub fn credentials<C: CredentialProvider + 'static>(mut self, credentials: C) -> Self {
+        self.credentials = Some(Credentials::Provider(Box::new(credentials)));
+        self
+    }
+    /// Authenticates with OAuth tokens from the origin's token endpoint, which are fetched
+    /// through the same proxy, timeouts and root certificates as API requests
+    pub fn oauth<I: Into<String>, S: Into<String>>(mut self, client_id: I, client_secret: S, request: TokenRequest) -> Self {
+        self.credentials = Some(Credentials::OAuth(client_id.into(), client_secret.into(), request));
+        self
+    }
+    /// Declares the scopes granted to the token, so that methods needing any other scope fail without a request
+    pub fn scopes(mut self, scopes: Vec<Scope>) -> Self {
+        self.scopes = Some(scopes);
+        self
+    }
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = Some(timeout);
+        self
+    }
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.write_timeout = Some(timeout);
+        self
+    }
+    /// Sends all requests through the HTTP proxy at `host:port`. HTTPS requests are tunneled with `CONNECT`
+    pub fn proxy<T: Into<String>>(mut self, host: T, port: u16) -> Self {
+        self.proxy = Some((host.into(), port));
+        self
+    }
+    /// Trusts an additional PEM encoded root certificate, e.g. a private CA for HipChat Server
+    pub fn add_root_certificate_pem<T: Into<Vec<u8>>>(mut self, pem: T) -> Self {
+        self.root_certificates.push(RootCertificate::Pem(pem.into()));
+        self
+    }
+    /// Trusts an additional DER encoded root certificate
+    pub fn add_root_certificate_der<T: Into<Vec<u8>>>(mut self, der: T) -> Self {
+        self.root_certificates.push(RootCertificate::Der(der.into()));
+        self
+    }
+    pub fn user_agent<T: Into<String>>(mut self, user_agent: T) -> Self {
+        self.headers.set(UserAgent(user_agent.into()));
+        self
+    }
+    /// Sets a header that is sent with every request
+    pub fn default_header<H: Header + HeaderFormat>(mut self, header: H) -> Self {
+        self.headers.set(header);
+        self
+    }
+    pub fn build(mut self) -> Result<Client, Error> {
+        match Url::parse(&self.origin) {
+            Ok(ref url) if url.scheme() == "https" && !tls::is_enabled() => {
+                return Err(Error::Validation(format!("origin {:?} requires the native-tls or rustls feature", self.origin)));
+            }
+            Ok(_) => (),
+            Err(e) => return Err(Error::Validation(format!("invalid origin {:?}: {}", self.origin, e)))
+        }
+
+        let credentials: Box<CredentialProvider> = match self.credentials.take() {
+            Some(Credentials::Provider(credentials)) => credentials,
+            Some(Credentials::OAuth(client_id, client_secret, request)) => {
+                let hyper_client = try!(self.hyper_client());
+                Box::new(OAuthCredentials::with_hyper_client(self.origin.as_str(), client_id, client_secret, request, hyper_client))
+            }
+            None => return Err(Error::Validation("a token or credentials provider is required".to_owned()))
+        };
+        let hyper_client = try!(self.hyper_client());
+
+        Ok(Client {
+            base_url: format!("{}/v2", self.origin.trim_end_matches('/')),
+            credentials: credentials,
+            scopes: self.scopes,
+            headers: Mutex::new(self.headers),
+            hyper_client: hyper_client,
+            capabilities: Mutex::new(None)
+        })
+    }
+    /// A Hyper client with this builder's proxy, timeouts and root certificates
+    pub(crate) fn hyper_client(&self) -> Result<HyperClient, Error> {
+        let ssl = try!(tls::tls_client(&self.root_certificates));
+
+        let connector = TimeoutConnector::new(self.connect_timeout);
+        let mut hyper_client = match self.proxy {
+            Some((ref host, port)) => HyperClient::with_proxy_config(ProxyConfig::new("http", host.clone(), port, connector, ssl)),
+            None => HyperClient::with_connector(HttpsConnector::with_connector(ssl, connector))
+        };
+        hyper_client.set_read_timeout(self.read_timeout);
+        hyper_client.set_write_timeout(self.write_timeout);
+        Ok(hyper_client)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use glance::GlanceLabel;
+    use test_server::{self, TestServer};
+
+    #[test]
+    fn unit_client_builder_requires_token() {
+        match ClientBuilder::new().build() {
+            Err(Error::Validation(_)) => (),
+            Err(e) => panic!("expected validation error, got {:?}", e),
+            Ok(_) => panic!("expected validation error")
+        }
+    }
+
+    #[test]
+    fn unit_client_builder_rejects_invalid_origin() {
+        assert!(ClientBuilder::new().origin("not a url").token("token").build().is_err());
+    }
+
+    #[test]
+    fn unit_client_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Client>();
+    }
+
+    #[test]
+    fn unit_client_builder_sets_default_headers() {
+        let client = ClientBuilder::new()
+            .origin("http://hipchat.example.com/")
+            .token("token")
+            .user_agent("ops-bot/1.0")
+            .proxy("proxy.example.com", 3128)
+            .connect_timeout(Duration::from_secs(5))
+            .build()
+            .unwrap();
+        assert_eq!(client.base_url, "http://hipchat.example.com/v2");
+        assert_eq!(client.headers.lock().unwrap().get::<UserAgent>(), Some(&UserAgent("ops-bot/1.0".to_owned())));
+    }
+
+    #[test]
+    fn unit_client_new_does_not_validate_origin() {
+        let client = Client::new("not a url", "token");
+        assert_eq!(client.base_url, "not a url/v2");
+        assert!(client.get_emoticon("allthethings").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "no-tls")]
+    fn unit_client_builder_rejects_https_without_tls() {
+        assert!(ClientBuilder::new().origin("https://api.hipchat.com").token("token").build().is_err());
+    }
+
+    #[test]
+    fn unit_client_refreshes_rejected_token() {
+        let token = |access_token: &str| test_server::json(&format!(
+            r#"{{"access_token":"{}","expires_in":3600,"group_id":1,"scope":"view_group"}}"#, access_token));
+        let server = TestServer::new(vec![
+            token("expired"),
+            test_server::response("401 Unauthorized", &[], ""),
+            token("fresh"),
+            test_server::json(r#"{"width":30,"audio_path":null,"id":1,"shortcut":"allthethings","height":"30"}"#)
+        ]);
+        let client = ClientBuilder::new()
+            .origin(server.origin.clone())
+            .oauth("id", "secret", TokenRequest::client_credentials(vec![]))
+            .build()
+            .unwrap();
+
+        let emoticon = client.get_emoticon("allthethings").unwrap();
+        assert_eq!(emoticon.shortcut, "allthethings");
+
+        let requests: Vec<_> = server.requests.iter().collect();
+        assert_eq!(requests[1].header("Authorization"), Some("Bearer expired"));
+        assert_eq!(requests[3].path, "/v2/emoticon/allthethings");
+        assert_eq!(requests[3].header("Authorization"), Some("Bearer fresh"));
+    }
+
+    #[test]
+    fn unit_client_builder_fetches_oauth_tokens_through_proxy() {
+        let proxy = TestServer::new(vec![
+            test_server::json(r#"{"access_token":"t","expires_in":3600,"group_id":1,"scope":"view_group"}"#),
+            test_server::json(r#"{"width":30,"audio_path":null,"id":1,"shortcut":"allthethings","height":"30"}"#)
+        ]);
+        let address = proxy.origin.trim_start_matches("http://").to_owned();
+        let port = address[address.rfind(':').unwrap() + 1..].parse().unwrap();
+        let client = ClientBuilder::new()
+            .origin("http://hipchat.example.com")
+            .proxy("127.0.0.1", port)
+            .oauth("id", "secret", TokenRequest::client_credentials(vec![Scope::ViewGroup]))
+            .build()
+            .unwrap();
+        client.get_emoticon("allthethings").unwrap();
+        assert_eq!(proxy.requests.recv().unwrap().path, "http://hipchat.example.com/v2/oauth/token");
+        assert_eq!(proxy.requests.recv().unwrap().path, "http://hipchat.example.com/v2/emoticon/allthethings");
+    }
+
+    #[test]
+    fn unit_client_checks_scopes_before_request() {
+        let client = ClientBuilder::new()
+            .origin("http://127.0.0.1:9")
+            .token("token")
+            .scopes(vec![Scope::ViewRoom])
+            .build()
+            .unwrap();
+        match client.send_notification("ops", &Notification::builder().message("hi").build().unwrap()) {
+            Err(Error::MissingScope(Scope::SendNotification)) => (),
+            other => panic!("expected missing scope error, got {:?}", other)
+        }
+        assert!(client.require_scopes(&[Scope::ViewRoom]).is_ok());
+    }
+
+    #[test]
+    fn unit_client_load_scopes() {
+        let server = TestServer::new(vec![
+            test_server::json(r#"{"id":"token","scopes":["view_group","send_message"]}"#)
+        ]);
+        let mut client = ClientBuilder::new().origin(server.origin.clone()).token("token").build().unwrap();
+        client.load_scopes().unwrap();
+        assert_eq!(client.scopes(), Some(&[Scope::ViewGroup, Scope::SendMessage][..]));
+        assert_eq!(server.requests.recv().unwrap().path, "/v2/oauth/token/token");
+        match client.require_scopes(&[Scope::SendMessage, Scope::AdminRoom]) {
+            Err(Error::MissingScope(Scope::AdminRoom)) => (),
+            other => panic!("expected missing scope error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn unit_client_update_room_glances() {
+        let server = TestServer::new(vec![test_server::response("204 No Content", &[], "")]);
+        let client = ClientBuilder::new().origin(server.origin.clone()).token("token").build().unwrap();
+        let glance = GlanceUpdate::new("build-health", GlanceLabel::text("All green"));
+        client.update_room_glances("42", &[glance]).unwrap();
+
+        let request = server.requests.recv().unwrap();
+        assert_eq!(request.path, "/v2/addon/ui/room/42");
+        assert_eq!(request.body, r#"{"glance":[{"key":"build-health","content":{"label":{"type":"html","value":"All green"}}}]}"#);
+    }
+
+    #[test]
+    fn unit_client_caches_capabilities() {
+        let server = TestServer::new(vec![
+            test_server::json(r#"{"name": "HipChat", "capabilities": {"oauth2Provider": {"authorizationUrl": "https://hipchat.example.com/users/authorize", "tokenUrl": "https://hipchat.example.com/v2/oauth/token"}}}"#)
+        ]);
+        let client = ClientBuilder::new().origin(server.origin.clone()).token("token").build().unwrap();
+        let capabilities = client.capabilities().unwrap();
+        assert_eq!(capabilities.token_url(), Some("https://hipchat.example.com/v2/oauth/token"));
+        assert_eq!(client.capabilities().unwrap(), capabilities);
+
+        assert_eq!(server.requests.recv().unwrap().path, "/v2/capabilities");
+        assert!(server.requests.try_recv().is_err());
+    }
+
+    #[test]
+    fn unit_client_uses_api_url_from_capabilities() {
+        let history = r#"{"items": [], "links": {"self": "https://api.hipchat.com/v2/group/1/history"}}"#;
+        let server = TestServer::new(vec![test_server::json(history), test_server::json(history)]);
+        let client = ClientBuilder::new().origin(server.origin.clone()).token("token").build().unwrap();
+        let advertise = |api_url: String| {
+            let capabilities = format!(r#"{{"name": "HipChat", "capabilities": {{"hipchatApiProvider": {{"url": "{}"}}}}}}"#, api_url);
+            *client.capabilities.lock().unwrap() = Some(serde_json::from_str(&capabilities).unwrap());
+        };
+
+        advertise(format!("{}/hipchat/v2/", server.origin));
+        client.view_group_history(1, None).unwrap();
+        assert_eq!(server.requests.recv().unwrap().path, "/hipchat/v2/group/1/history");
+
+        advertise("https://elsewhere.example.com/v2/".to_owned());
+        client.view_group_history(1, None).unwrap();
+        assert_eq!(server.requests.recv().unwrap().path, "/v2/group/1/history");
+    }
+
+    #[test]
+    fn unit_client_download_file_only_authenticates_own_origin() {
+        let server = TestServer::new(vec![test_server::response("200 OK", &[], "own")]);
+        let files = TestServer::new(vec![test_server::response("200 OK", &[], "elsewhere")]);
+        let client = ClientBuilder::new().origin(server.origin.clone()).token("token").build().unwrap();
+
+        let mut own = Vec::new();
+        assert_eq!(client.download_file(&format!("{}/files/1/a.txt", server.origin), &mut own).unwrap(), 3);
+        assert_eq!(server.requests.recv().unwrap().header("Authorization"), Some("Bearer token"));
+
+        let mut elsewhere = Vec::new();
+        client.download_file(&format!("{}/files/1/b.txt", files.origin), &mut elsewhere).unwrap();
+        assert_eq!(elsewhere, b"elsewhere");
+        assert_eq!(files.requests.recv().unwrap().header("Authorization"), None);
+    }
+
+    #[test]
+    fn unit_client_view_group_history() {
+        let server = TestServer::new(vec![
+            test_server::json(r#"{"items": [], "links": {"self": "https://api.hipchat.com/v2/group/42/history"}}"#)
+        ]);
+        let client = ClientBuilder::new().origin(server.origin.clone()).token("token").build().unwrap();
+        let req = MessagesRequest::builder().date("2017-03-01T00:00:00Z").end_date("2017-02-01T00:00:00Z").build().unwrap();
+        assert!(client.view_group_history(42, Some(&req)).unwrap().items.is_empty());
+        assert_eq!(server.requests.recv().unwrap().path, "/v2/group/42/history?date=2017-03-01T00%3A00%3A00Z&end-date=2017-02-01T00%3A00%3A00Z");
+    }
+
+    #[test]
+    fn unit_client_invite_user_to_room_maps_api_errors() {
+        let server = TestServer::new(vec![
+            test_server::response("204 No Content", &[], ""),
+            test_server::response("409 Conflict", &[("Content-Type", "application/json")],
+                                  r#"{"error": {"code": 409, "message": "User is already a member of the room", "type": "Conflict"}}"#),
+            test_server::response("500 Internal Server Error", &[], "oops")
+        ]);
+        let client = ClientBuilder::new().origin(server.origin.clone()).token("token").build().unwrap();
+        client.invite_user_to_room("Ops", "ada@example.com", "on call").unwrap();
+        let request = server.requests.recv().unwrap();
+        assert_eq!(request.path, "/v2/room/Ops/invite/ada@example.com");
+        assert_eq!(request.body, r#"{"reason":"on call"}"#);
+
+        match client.invite_user_to_room("Ops", "ada@example.com", "on call") {
+            Err(Error::Api(ref e)) if e.is_already_member() => assert_eq!(e.error_type, Some("Conflict".to_owned())),
+            other => panic!("expected already a member error, got {:?}", other)
+        }
+        match client.invite_user_to_room("Ops", "ada@example.com", "on call") {
+            Err(Error::HttpStatus(StatusCode::InternalServerError)) => (),
+            other => panic!("expected status error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn unit_client_invite_user_to_group() {
+        let server = TestServer::new(vec![
+            test_server::json(r#"{"id": 7, "links": {"self": "https://api.hipchat.com/v2/user/7"}}"#)
+        ]);
+        let client = ClientBuilder::new().origin(server.origin.clone()).token("token").build().unwrap();
+        assert_eq!(client.invite_user_to_group("ada@example.com", "Ada", None).unwrap().id, 7);
+        let request = server.requests.recv().unwrap();
+        assert_eq!(request.path, "/v2/invite/user");
+        assert_eq!(request.body, r#"{"email":"ada@example.com","name":"Ada"}"#);
+    }
+
+    #[test]
+    fn unit_client_get_user_conditional() {
+        let user = r#"{"id": 1, "name": "Ada", "mention_name": "ada", "links": {"self": "https://api.hipchat.com/v2/user/1"}}"#;
+        let server = TestServer::new(vec![
+            test_server::response("200 OK", &[("ETag", "\"v1\""), ("Last-Modified", "Wed, 01 Mar 2017 12:00:00 GMT")], user),
+            test_server::response("304 Not Modified", &[("ETag", "\"v1\"")], "")
+        ]);
+        let client = ClientBuilder::new().origin(server.origin.clone()).token("token").build().unwrap();
+
+        let first = client.get_user_conditional("ada", None).unwrap();
+        assert_eq!(first.meta().last_modified, Some("Wed, 01 Mar 2017 12:00:00 GMT".to_owned()));
+        let etag = first.meta().etag.clone().unwrap();
+        assert_eq!(first.into_modified().unwrap().id, 1);
+
+        let second = client.get_user_conditional("ada", Some(&etag)).unwrap();
+        assert_eq!(second, Conditional::NotModified(ResponseMeta { etag: Some(etag), last_modified: None }));
+        assert_eq!(server.requests.recv().unwrap().header("If-None-Match"), None);
+        assert_eq!(server.requests.recv().unwrap().header("If-None-Match"), Some("\"v1\""));
+    }
+
+    #[test]
+    fn unit_client_modify_room_retries_on_precondition_failure() {
+        let room = |topic: &str| format!(r#"{{
+            "xmpp_jid": "1_ops@conf.hipchat.com", "name": "Ops", "id": 1, "created": "2016-01-01T00:00:00+00:00",
+            "is_archived": false, "privacy": "public", "is_guest_accessible": false, "topic": "{}",
+            "avatar_url": null, "guest_access_url": null,
+            "owner": {{"id": 5, "name": "Ada", "mention_name": "ada", "links": {{"self": "https://api.hipchat.com/v2/user/5"}}}},
+            "statistics": {{"links": {{"self": "https://api.hipchat.com/v2/room/1/statistics"}}}},
+            "links": {{"self": "https://api.hipchat.com/v2/room/1", "webhooks": "w", "participants": "p"}}
+        }}"#, topic);
+        let server = TestServer::new(vec![
+            test_server::response("200 OK", &[("ETag", "\"v1\"")], &room("old")),
+            test_server::response("412 Precondition Failed", &[], ""),
+            test_server::response("200 OK", &[("ETag", "\"v2\"")], &room("changed")),
+            test_server::response("204 No Content", &[], "")
+        ]);
+        let client = ClientBuilder::new().origin(server.origin.clone()).token("token").build().unwrap();
+        let mut seen = Vec::new();
+        client.modify_room("Ops", |room| {
+            seen.push(room.topic.clone());
+            room.topic.push_str(" (on call: ada)");
+        }).unwrap();
+        assert_eq!(seen, vec!["old", "changed"]);
+
+        server.requests.recv().unwrap();
+        assert_eq!(server.requests.recv().unwrap().header("If-Match"), Some("\"v1\""));
+        server.requests.recv().unwrap();
+        let update = server.requests.recv().unwrap();
+        assert_eq!(update.path, "/v2/room/1");
+        assert_eq!(update.header("If-Match"), Some("\"v2\""));
+        assert!(update.body.contains(r#""topic":"changed (on call: ada)""#));
+        assert!(update.body.contains(r#""owner":{"id":"5"}"#));
+    }
+
+    #[test]
+    fn unit_client_patch_room_rejects_room_without_owner() {
+        let room = r#"{
+            "xmpp_jid": "1_ops@conf.hipchat.com", "name": "Ops", "id": 1, "created": "2016-01-01T00:00:00+00:00",
+            "is_archived": false, "privacy": "public", "is_guest_accessible": false, "topic": "t",
+            "avatar_url": null, "guest_access_url": null, "owner": null,
+            "statistics": {"links": {"self": "https://api.hipchat.com/v2/room/1/statistics"}},
+            "links": {"self": "https://api.hipchat.com/v2/room/1", "webhooks": "w", "participants": "p"}
+        }"#;
+        let server = TestServer::new(vec![test_server::json(room), test_server::response("204 No Content", &[], "")]);
+        let client = ClientBuilder::new().origin(server.origin.clone()).token("token").build().unwrap();
+        let patch = RoomUpdate::builder().topic("new").build().unwrap();
+        match client.patch_room("Ops", &patch) {
+            Err(Error::Validation(_)) => (),
+            other => panic!("expected validation error, got {:?}", other)
+        }
+        assert_eq!(server.requests.recv().unwrap().method, "GET");
+        assert!(server.requests.try_recv().is_err());
+    }
 }
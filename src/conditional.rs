@@ -0,0 +1,56 @@
+//! Conditional GETs, so pollers can skip documents that have not changed
+
+use hyper::client::Response;
+
+/// Validators HipChat sent with a document
+#[derive(Debug, Clone, Default, Hash, Eq, PartialEq)]
+pub struct ResponseMeta {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>
+}
+
+impl ResponseMeta {
+    pub(crate) fn from_response(res: &Response) -> ResponseMeta {
+        ResponseMeta {
+            etag: raw_header(res, "ETag"),
+            last_modified: raw_header(res, "Last-Modified")
+        }
+    }
+}
+
+/// The outcome of a GET sent with `If-None-Match`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Conditional<T> {
+    /// The document changed, or no ETag was sent
+    Modified(T, ResponseMeta),
+    /// HipChat answered `304 Not Modified`
+    NotModified(ResponseMeta)
+}
+
+impl<T> Conditional<T> {
+    pub fn meta(&self) -> &ResponseMeta {
+        match *self {
+            Conditional::Modified(_, ref meta) => meta,
+            Conditional::NotModified(ref meta) => meta
+        }
+    }
+    pub fn is_modified(&self) -> bool {
+        match *self {
+            Conditional::Modified(..) => true,
+            Conditional::NotModified(_) => false
+        }
+    }
+    /// The document, if it was sent
+    pub fn into_modified(self) -> Option<T> {
+        match self {
+            Conditional::Modified(value, _) => Some(value),
+            Conditional::NotModified(_) => None
+        }
+    }
+}
+
+fn raw_header(res: &Response, name: &str) -> Option<String> {
+    res.headers.get_raw(name)
+        .and_then(|values| values.first())
+        .and_then(|value| String::from_utf8(value.clone()).ok())
+}
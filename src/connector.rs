@@ -0,0 +1,63 @@
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use hyper;
+use hyper::net::{HttpStream, NetworkConnector};
+
+/// An HTTP connector that bounds the time spent establishing each TCP connection
+#[derive(Debug, Clone, Default)]
+pub struct TimeoutConnector {
+    connect_timeout: Option<Duration>
+}
+
+impl TimeoutConnector {
+    pub fn new(connect_timeout: Option<Duration>) -> Self {
+        TimeoutConnector {
+            connect_timeout: connect_timeout
+        }
+    }
+}
+
+impl NetworkConnector for TimeoutConnector {
+    type Stream = HttpStream;
+
+    fn connect(&self, host: &str, port: u16, scheme: &str) -> hyper::Result<HttpStream> {
+        if scheme != "http" {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid scheme for Http").into());
+        }
+        let timeout = match self.connect_timeout {
+            Some(timeout) => timeout,
+            None => return Ok(HttpStream(try!(TcpStream::connect((host, port)))))
+        };
+
+        let mut last_err = io::Error::new(io::ErrorKind::InvalidInput, "Could not resolve host");
+        for addr in try!((host, port).to_socket_addrs()) {
+            match TcpStream::connect_timeout(&addr, timeout) {
+                Ok(stream) => return Ok(HttpStream(stream)),
+                Err(e) => last_err = e
+            }
+        }
+        Err(last_err.into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn unit_timeout_connector_connects() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let connector = TimeoutConnector::new(Some(Duration::from_secs(5)));
+        assert!(connector.connect("127.0.0.1", port, "http").is_ok());
+    }
+
+    #[test]
+    fn unit_timeout_connector_rejects_https_scheme() {
+        let connector = TimeoutConnector::new(None);
+        assert!(connector.connect("127.0.0.1", 443, "https").is_err());
+    }
+}
@@ -1,8 +1,47 @@
+use serde::{Deserialize, Deserializer};
+use serde::de;
+use serde_json::Value;
+
 #[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Emoticon {
     pub width: u64,
     pub audio_path: Option<String>,
     pub id: u64,
     pub shortcut: String,
+    /// Sent as a number by HipChat Cloud and as a string by some HipChat Server versions
+    #[serde(deserialize_with = "string_or_number")]
     pub height: String
 }
+
+fn string_or_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    match try!(Value::deserialize(deserializer)) {
+        Value::String(s) => Ok(s),
+        Value::Number(n) => Ok(n.to_string()),
+        other => Err(de::Error::custom(format!("expected a string or number, got {}", other)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::{self};
+
+    #[test]
+    fn unit_deserialize_emoticon_fixture() {
+        let emoticon: Emoticon = serde_json::from_str(r#"{
+            "audio_path": null,
+            "height": 30,
+            "id": 41,
+            "links": {"self": "https://api.hipchat.com/v2/emoticon/41"},
+            "shortcut": "allthethings",
+            "type": "global",
+            "url": "https://dujrsrsgsd3nh.cloudfront.net/img/emoticons/allthethings.png",
+            "width": 30
+        }"#).unwrap();
+        assert_eq!(emoticon.shortcut, "allthethings");
+        assert_eq!(emoticon.height, "30");
+
+        let emoticon: Emoticon = serde_json::from_str(r#"{"audio_path": null, "height": "25", "id": 1, "shortcut": "a", "width": 25}"#).unwrap();
+        assert_eq!(emoticon.height, "25");
+    }
+}
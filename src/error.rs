@@ -4,14 +4,57 @@ use std::fmt::{Display, Formatter};
 use std::io::Error as IoError;
 use hyper::error::Error as HyperError;
 use hyper::status::StatusCode;
+use serde_json::{self};
 use serde_json::error::Error as JsonError;
 
+use auth::Scope;
+
 #[derive(Debug)]
 pub enum Error {
     Io(IoError),
     Json(JsonError),
     Http(HyperError),
-    HttpStatus(StatusCode)
+    HttpStatus(StatusCode),
+    Validation(String),
+    Tls(Box<StdError + Send + Sync>),
+    MissingScope(Scope),
+    InvalidToken(String),
+    Api(ApiError)
+}
+
+/// An error reported by HipChat in the response body
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub message: String,
+    pub error_type: Option<String>
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    error: ErrorDetail
+}
+
+#[derive(Deserialize)]
+struct ErrorDetail {
+    message: String,
+    #[serde(rename = "type")]
+    error_type: Option<String>
+}
+
+impl ApiError {
+    /// Parses a `{"error": {"message": ..., "type": ...}}` response body
+    pub fn parse(status: StatusCode, body: &str) -> Option<ApiError> {
+        serde_json::from_str::<ErrorBody>(body).ok().map(|body| ApiError {
+            status: status,
+            message: body.error.message,
+            error_type: body.error.error_type
+        })
+    }
+    /// Whether the user being invited or added is already a member
+    pub fn is_already_member(&self) -> bool {
+        self.status == StatusCode::Conflict || self.message.to_lowercase().contains("already a member")
+    }
 }
 
 impl From<IoError> for Error {
@@ -44,7 +87,12 @@ impl Display for Error {
             Error::Io(ref e) => Display::fmt(e, f),
             Error::Json(ref e) => Display::fmt(e, f),
             Error::Http(ref e) => Display::fmt(e, f),
-            Error::HttpStatus(e) => f.write_fmt(format_args!("Unexpected status code: {}", e))
+            Error::HttpStatus(e) => f.write_fmt(format_args!("Unexpected status code: {}", e)),
+            Error::Validation(ref e) => f.write_fmt(format_args!("Invalid request: {}", e)),
+            Error::Tls(ref e) => Display::fmt(e, f),
+            Error::MissingScope(scope) => f.write_fmt(format_args!("Token is missing the required {} scope", scope)),
+            Error::InvalidToken(ref e) => f.write_fmt(format_args!("Invalid JWT: {}", e)),
+            Error::Api(ref e) => f.write_fmt(format_args!("HipChat returned {}: {}", e.status, e.message))
         }
    }
 }
@@ -59,7 +107,12 @@ impl StdError for Error {
             Error::Io(ref e) => Some(e),
             Error::Json(ref e) => Some(e),
             Error::Http(ref e) => Some(e),
-            Error::HttpStatus(_) => None
+            Error::HttpStatus(_) => None,
+            Error::Validation(_) => None,
+            Error::Tls(ref e) => Some(&**e),
+            Error::MissingScope(_) => None,
+            Error::InvalidToken(_) => None,
+            Error::Api(_) => None
         }
     }
 }
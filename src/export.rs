@@ -0,0 +1,363 @@
+//! Full room history export to JSON Lines or CSV
+//!
+//! History is read newest first, one window of up to 1000 messages at a time,
+//! so each window in the output is in chronological order but later windows
+//! hold older messages. With a checkpoint file an interrupted export carries
+//! on where it stopped, appending to the same output. `export_to_file` first
+//! cuts the file back to the length recorded in the checkpoint, so messages
+//! written after the last checkpoint are not written twice.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+use serde_json::{self};
+
+use client::Client;
+use error::Error;
+use message::{Message, MessagesRequest};
+use util::MAX_RESULTS_LIMIT;
+
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+pub enum ExportFormat {
+    /// One `Message` as JSON per line
+    JsonLines,
+    /// `id,date,from,type,message,file` with a header row
+    Csv
+}
+
+const CSV_HEADER: &str = "id,date,from,type,message,file";
+
+/// Progress of an export, saved after each window
+#[derive(Debug, Clone, Default, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub room: String,
+    /// Date of the oldest message written so far
+    pub cursor: Option<String>,
+    /// Ids of the written messages dated exactly `cursor`, which the next window returns again
+    pub boundary_ids: Vec<String>,
+    pub exported: u64,
+    /// Bytes of output written up to this checkpoint, unknown for checkpoints from older versions
+    pub output_len: Option<u64>,
+    pub complete: bool
+}
+
+impl Checkpoint {
+    pub fn load(path: &Path) -> Result<Option<Checkpoint>, Error> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let mut body = String::new();
+        try!(try!(File::open(path)).read_to_string(&mut body));
+        Ok(Some(try!(serde_json::from_str(&body))))
+    }
+    /// Writes the checkpoint next to `path` and renames it into place
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let tmp = path.with_extension("tmp");
+        try!(try!(File::create(&tmp)).write_all(serde_json::to_string(self).unwrap().as_bytes()));
+        try!(fs::rename(&tmp, path));
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, Hash, Eq, PartialEq)]
+pub struct ExportSummary {
+    /// Messages written by this run
+    pub messages: u64,
+    /// Files downloaded by this run
+    pub files: u64,
+    /// Messages written by this and earlier runs resumed from the checkpoint
+    pub total: u64
+}
+
+/// Exports every message in a room
+///
+/// `C` is anything that derefs to a `Client`, such as `&Client` or `Arc<Client>`.
+pub struct RoomExporter<C: Deref<Target = Client>> {
+    client: C,
+    room: String,
+    format: ExportFormat,
+    since: Option<String>,
+    files_dir: Option<PathBuf>,
+    checkpoint: Option<PathBuf>
+}
+
+impl<C: Deref<Target = Client>> RoomExporter<C> {
+    pub fn new<T: Into<String>>(client: C, room_id_or_name: T) -> Self {
+        RoomExporter {
+            client: client,
+            room: room_id_or_name.into(),
+            format: ExportFormat::JsonLines,
+            since: None,
+            files_dir: None,
+            checkpoint: None
+        }
+    }
+    /// Defaults to `ExportFormat::JsonLines`
+    pub fn format(mut self, format: ExportFormat) -> Self {
+        self.format = format;
+        self
+    }
+    /// Stops at messages older than this date, sent to HipChat as `end-date`
+    pub fn since<T: Into<String>>(mut self, date: T) -> Self {
+        self.since = Some(date.into());
+        self
+    }
+    /// Downloads attached files into `dir`, named `{message id}-{file name}`
+    pub fn download_files<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.files_dir = Some(dir.into());
+        self
+    }
+    /// Saves progress to `path` after each window and resumes from it if it exists
+    pub fn checkpoint<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.checkpoint = Some(path.into());
+        self
+    }
+    /// Exports to a file, appending when resuming from a checkpoint
+    ///
+    /// Output written after the checkpoint was last saved is dropped first.
+    pub fn export_to_file<P: AsRef<Path>>(&self, path: P) -> Result<ExportSummary, Error> {
+        let checkpoint = match self.checkpoint {
+            Some(ref checkpoint) => try!(Checkpoint::load(checkpoint)),
+            None => None
+        };
+        let resuming = checkpoint.as_ref().map(|c| c.exported > 0).unwrap_or(false);
+        let mut file = try!(OpenOptions::new().create(true).write(true).truncate(!resuming).open(path));
+        if resuming {
+            if let Some(output_len) = checkpoint.and_then(|c| c.output_len) {
+                try!(file.set_len(output_len));
+            }
+            try!(file.seek(SeekFrom::End(0)));
+        }
+        self.export(&mut file)
+    }
+    /// Exports to `out`. The CSV header is only written when not resuming
+    pub fn export<W: Write>(&self, out: &mut W) -> Result<ExportSummary, Error> {
+        let mut checkpoint = match self.checkpoint {
+            Some(ref path) => try!(Checkpoint::load(path)),
+            None => None
+        }.unwrap_or_else(|| Checkpoint { room: self.room.clone(), output_len: Some(0), ..Checkpoint::default() });
+        if checkpoint.room != self.room {
+            return Err(Error::Validation(format!("checkpoint is for room {:?}, not {:?}", checkpoint.room, self.room)));
+        }
+
+        let mut summary = ExportSummary { total: checkpoint.exported, ..ExportSummary::default() };
+        if checkpoint.complete {
+            return Ok(summary);
+        }
+        let mut written = 0;
+        if self.format == ExportFormat::Csv && checkpoint.exported == 0 {
+            written += try!(write_line(out, CSV_HEADER));
+        }
+        if let Some(ref dir) = self.files_dir {
+            try!(fs::create_dir_all(dir));
+        }
+
+        loop {
+            let req = MessagesRequest {
+                max_results: Some(MAX_RESULTS_LIMIT),
+                date: checkpoint.cursor.clone(),
+                end_date: self.since.clone(),
+                ..MessagesRequest::default()
+            };
+            let mut messages = try!(self.client.get_room_history(&self.room, Some(&req))).items;
+            messages.retain(|message| is_new(&checkpoint, message));
+            if messages.is_empty() {
+                checkpoint.complete = true;
+                try!(self.save(&checkpoint));
+                return Ok(summary);
+            }
+            messages.sort_by(|a, b| a.date.cmp(&b.date));
+
+            for message in &messages {
+                written += try!(self.write(out, message));
+                if let Some(ref dir) = self.files_dir {
+                    if try!(self.download(dir, message)) {
+                        summary.files += 1;
+                    }
+                }
+            }
+            try!(out.flush());
+
+            let oldest = messages[0].date.clone();
+            if checkpoint.cursor.as_ref() != Some(&oldest) {
+                checkpoint.boundary_ids.clear();
+            }
+            checkpoint.boundary_ids.extend(messages.iter().filter(|m| m.date == oldest).map(|m| m.id.clone()));
+            checkpoint.cursor = Some(oldest);
+            checkpoint.exported += messages.len() as u64;
+            checkpoint.output_len = checkpoint.output_len.map(|output_len| output_len + written);
+            written = 0;
+            summary.messages += messages.len() as u64;
+            summary.total = checkpoint.exported;
+            try!(self.save(&checkpoint));
+        }
+    }
+    fn save(&self, checkpoint: &Checkpoint) -> Result<(), Error> {
+        match self.checkpoint {
+            Some(ref path) => checkpoint.save(path),
+            None => Ok(())
+        }
+    }
+    /// Writes one message and returns the bytes written
+    fn write<W: Write>(&self, out: &mut W, message: &Message) -> Result<u64, Error> {
+        match self.format {
+            ExportFormat::JsonLines => write_line(out, &serde_json::to_string(message).unwrap()),
+            ExportFormat::Csv => {
+                let message_type = serde_json::to_value(&message.message_type).unwrap();
+                let fields = [
+                    message.id.as_str(),
+                    message.date.as_str(),
+                    message.sender_name().unwrap_or(""),
+                    message_type.as_str().unwrap_or(""),
+                    message.message.as_str(),
+                    message.file.as_ref().map_or("", |file| file.url.as_str())
+                ];
+                let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+                write_line(out, &row.join(","))
+            }
+        }
+    }
+    fn download(&self, dir: &Path, message: &Message) -> Result<bool, Error> {
+        let file = match message.file {
+            Some(ref file) => file,
+            None => return Ok(false)
+        };
+        let path = dir.join(format!("{}-{}", message.id, sanitize_file_name(&file.name)));
+        if path.exists() {
+            return Ok(false);
+        }
+        let tmp = path.with_extension("part");
+        try!(self.client.download_file(&file.url, &mut try!(File::create(&tmp))));
+        try!(fs::rename(&tmp, &path));
+        Ok(true)
+    }
+}
+
+/// Whether a message is older than everything written so far
+fn is_new(checkpoint: &Checkpoint, message: &Message) -> bool {
+    match checkpoint.cursor {
+        Some(ref cursor) if message.date > *cursor => false,
+        Some(ref cursor) if message.date == *cursor => !checkpoint.boundary_ids.contains(&message.id),
+        _ => true
+    }
+}
+
+fn write_line<W: Write>(out: &mut W, line: &str) -> Result<u64, Error> {
+    try!(writeln!(out, "{}", line));
+    Ok(line.len() as u64 + 1)
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+fn sanitize_file_name(name: &str) -> String {
+    name.replace(['/', '\\', '\0'], "_")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use test_server::{self, TestServer};
+
+    fn history(messages: &[(&str, &str)]) -> String {
+        let messages: Vec<String> = messages.iter()
+            .map(|&(id, day)| test_server::message(id, &format!("2017-03-0{}T12:00:00.000000+00:00", day), "hi, \"all\""))
+            .collect();
+        test_server::history(&messages)
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("hipchat-export-{}-{}", ::std::process::id(), name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn ids(output: &[u8]) -> Vec<String> {
+        String::from_utf8(output.to_vec()).unwrap().lines()
+            .map(|line| serde_json::from_str::<Message>(line).unwrap().id)
+            .collect()
+    }
+
+    #[test]
+    fn unit_export_walks_back_and_deduplicates() {
+        let server = TestServer::new(vec![
+            history(&[("c", "3"), ("d", "4"), ("e", "5")]),
+            history(&[("a", "1"), ("b", "3"), ("c", "3")]),
+            history(&[("a", "1")])
+        ]);
+        let client = test_server::client(&server);
+        let mut output = Vec::new();
+        let summary = RoomExporter::new(&client, "Ops").export(&mut output).unwrap();
+
+        assert_eq!(ids(&output), vec!["c", "d", "e", "a", "b"]);
+        assert_eq!(summary.messages, 5);
+        assert_eq!(server.requests.recv().unwrap().path, "/v2/room/Ops/history?max-results=1000");
+        assert_eq!(server.requests.recv().unwrap().path, "/v2/room/Ops/history?max-results=1000&date=2017-03-03T12%3A00%3A00.000000%2B00%3A00");
+    }
+
+    #[test]
+    fn unit_export_resumes_from_checkpoint() {
+        let checkpoint_path = temp_path("checkpoint.json");
+        Checkpoint {
+            room: "Ops".to_owned(),
+            cursor: Some("2017-03-03T12:00:00.000000+00:00".to_owned()),
+            boundary_ids: vec!["c".to_owned()],
+            exported: 3,
+            output_len: None,
+            complete: false
+        }.save(&checkpoint_path).unwrap();
+
+        let server = TestServer::new(vec![history(&[("b", "3"), ("c", "3")]), history(&[])]);
+        let client = test_server::client(&server);
+        let mut output = Vec::new();
+        let summary = RoomExporter::new(&client, "Ops")
+            .format(ExportFormat::Csv)
+            .checkpoint(&checkpoint_path)
+            .export(&mut output)
+            .unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), "b,2017-03-03T12:00:00.000000+00:00,CI,notification,\"hi, \"\"all\"\"\",\n");
+        assert_eq!(summary.total, 4);
+        let saved = Checkpoint::load(&checkpoint_path).unwrap().unwrap();
+        assert!(saved.complete);
+        assert_eq!(saved.boundary_ids, vec!["c", "b"]);
+        fs::remove_file(&checkpoint_path).unwrap();
+    }
+
+    #[test]
+    fn unit_export_to_file_drops_output_after_checkpoint() {
+        let checkpoint_path = temp_path("truncate-checkpoint.json");
+        let output_path = temp_path("truncate-output.jsonl");
+        let written = "{\"id\": \"c\"}\n";
+        fs::write(&output_path, format!("{}{}", written, "{\"id\": \"b\"}\n")).unwrap();
+        Checkpoint {
+            room: "Ops".to_owned(),
+            cursor: Some("2017-03-03T12:00:00.000000+00:00".to_owned()),
+            boundary_ids: vec!["c".to_owned()],
+            exported: 1,
+            output_len: Some(written.len() as u64),
+            complete: false
+        }.save(&checkpoint_path).unwrap();
+
+        let server = TestServer::new(vec![history(&[("b", "3"), ("c", "3")]), history(&[])]);
+        let client = test_server::client(&server);
+        RoomExporter::new(&client, "Ops").checkpoint(&checkpoint_path).export_to_file(&output_path).unwrap();
+
+        let output = fs::read_to_string(&output_path).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(serde_json::from_str::<Message>(lines[1]).unwrap().id, "b");
+        assert_eq!(Checkpoint::load(&checkpoint_path).unwrap().unwrap().output_len, Some(output.len() as u64));
+        fs::remove_file(&checkpoint_path).unwrap();
+        fs::remove_file(&output_path).unwrap();
+    }
+}
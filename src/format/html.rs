@@ -0,0 +1,353 @@
+//! Safe construction of `MessageFormat::Html` message bodies
+//!
+//! HipChat renders only a [restricted set of tags](https://www.hipchat.com/docs/apiv2/method/send_room_notification).
+//! `Html` escapes every piece of text it is given and only ever emits those
+//! tags, so user input cannot inject markup.
+//!
+//! ```
+//! use hipchat_client::format::html::Html;
+//!
+//! let user_input = "<script>alert(1)</script>";
+//! let body = Html::new()
+//!     .bold("Build failed: ")
+//!     .link("https://ci.example.com/builds/42", user_input)
+//!     .build()
+//!     .unwrap();
+//! assert_eq!(body, "<b>Build failed: </b><a href=\"https://ci.example.com/builds/42\">&lt;script&gt;alert(1)&lt;/script&gt;</a>");
+//! ```
+
+use error::Error;
+use message::{Color, MessageFormat};
+use room::{Notification, NOTIFICATION_MAX_LENGTH};
+use util::validate_length;
+
+/// Tags HipChat renders in HTML messages
+pub const ALLOWED_TAGS: &[&str] = &[
+    "a", "b", "i", "strong", "em", "br", "img", "pre", "code", "ul", "ol", "li", "table", "tr", "td"
+];
+
+/// Escapes text for use in HTML content and attribute values
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c)
+        }
+    }
+    escaped
+}
+
+/// Content that can be placed inside an `Html` element. Text is escaped, `Html` is kept as is
+pub trait IntoHtml {
+    fn into_html(self) -> String;
+}
+
+impl IntoHtml for &str {
+    fn into_html(self) -> String {
+        escape(self)
+    }
+}
+
+impl IntoHtml for String {
+    fn into_html(self) -> String {
+        escape(&self)
+    }
+}
+
+impl IntoHtml for &String {
+    fn into_html(self) -> String {
+        escape(self)
+    }
+}
+
+impl IntoHtml for Html {
+    fn into_html(self) -> String {
+        self.markup
+    }
+}
+
+/// A fragment of HipChat HTML. Invalid input is reported by `build`
+#[derive(Debug, Clone, Default, Hash, Eq, PartialEq)]
+pub struct Html {
+    markup: String,
+    error: Option<String>
+}
+
+impl Html {
+    pub fn new() -> Self {
+        Html::default()
+    }
+    /// Accepts existing markup after checking that it only uses allowed tags and attributes
+    pub fn raw<T: Into<String>>(markup: T) -> Self {
+        let markup = markup.into();
+        let error = check_markup(&markup).err();
+        Html { markup: markup, error: error }
+    }
+    pub fn text<T: IntoHtml>(self, text: T) -> Self {
+        self.push(text)
+    }
+    pub fn bold<T: IntoHtml>(self, content: T) -> Self {
+        self.element("b", content)
+    }
+    pub fn italic<T: IntoHtml>(self, content: T) -> Self {
+        self.element("i", content)
+    }
+    pub fn code<T: IntoHtml>(self, content: T) -> Self {
+        self.element("code", content)
+    }
+    pub fn pre<T: IntoHtml>(self, content: T) -> Self {
+        self.element("pre", content)
+    }
+    pub fn line_break(mut self) -> Self {
+        self.markup.push_str("<br>");
+        self
+    }
+    /// Links to an `http`, `https` or `mailto` URL
+    pub fn link<T: IntoHtml>(mut self, href: &str, content: T) -> Self {
+        if !has_safe_scheme(href, &["http", "https", "mailto"]) {
+            return self.fail(format!("unsupported link URL {:?}", href));
+        }
+        self.markup.push_str(&format!("<a href=\"{}\">", escape(href)));
+        self.push(content).close("a")
+    }
+    /// Embeds an `http` or `https` image
+    pub fn image(mut self, src: &str, alt: &str) -> Self {
+        if !has_safe_scheme(src, &["http", "https"]) {
+            return self.fail(format!("unsupported image URL {:?}", src));
+        }
+        self.markup.push_str(&format!("<img src=\"{}\" alt=\"{}\">", escape(src), escape(alt)));
+        self
+    }
+    pub fn list<I: IntoIterator<Item = T>, T: IntoHtml>(self, items: I) -> Self {
+        self.list_of("ul", items)
+    }
+    pub fn ordered_list<I: IntoIterator<Item = T>, T: IntoHtml>(self, items: I) -> Self {
+        self.list_of("ol", items)
+    }
+    /// A table whose first row is rendered in bold as the header
+    pub fn table<R, C, T>(mut self, header: &[&str], rows: R) -> Self
+        where R: IntoIterator<Item = C>, C: IntoIterator<Item = T>, T: IntoHtml
+    {
+        self.markup.push_str("<table>");
+        if !header.is_empty() {
+            self.markup.push_str("<tr>");
+            for cell in header {
+                self.markup.push_str(&format!("<td><b>{}</b></td>", escape(cell)));
+            }
+            self.markup.push_str("</tr>");
+        }
+        for row in rows {
+            self.markup.push_str("<tr>");
+            for cell in row {
+                self = self.element("td", cell);
+            }
+            self.markup.push_str("</tr>");
+        }
+        self.close("table")
+    }
+    /// The markup, or an error for unsafe input or if it exceeds the notification length limit
+    pub fn build(self) -> Result<String, Error> {
+        if let Some(error) = self.error {
+            return Err(Error::Validation(error));
+        }
+        try!(validate_length("message", &self.markup, 1, NOTIFICATION_MAX_LENGTH));
+        Ok(self.markup)
+    }
+    /// Builds an HTML room notification with this body
+    pub fn notification(self, color: Color) -> Result<Notification, Error> {
+        Notification::builder()
+            .color(color)
+            .message_format(MessageFormat::Html)
+            .message(try!(self.build()))
+            .build()
+    }
+    fn list_of<I: IntoIterator<Item = T>, T: IntoHtml>(mut self, tag: &str, items: I) -> Self {
+        self.markup.push_str(&format!("<{}>", tag));
+        for item in items {
+            self = self.element("li", item);
+        }
+        self.close(tag)
+    }
+    fn element<T: IntoHtml>(mut self, tag: &str, content: T) -> Self {
+        self.markup.push_str(&format!("<{}>", tag));
+        self.push(content).close(tag)
+    }
+    fn push<T: IntoHtml>(mut self, content: T) -> Self {
+        self.markup.push_str(&content.into_html());
+        self
+    }
+    fn close(mut self, tag: &str) -> Self {
+        self.markup.push_str(&format!("</{}>", tag));
+        self
+    }
+    fn fail(mut self, error: String) -> Self {
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+        self
+    }
+}
+
+fn has_safe_scheme(url: &str, schemes: &[&str]) -> bool {
+    match url.find(':') {
+        Some(i) => schemes.iter().any(|scheme| url[..i].eq_ignore_ascii_case(scheme)),
+        None => false
+    }
+}
+
+/// Checks that markup only uses `ALLOWED_TAGS`, with `href` on links and `src`/`alt` on images
+pub fn check_markup(markup: &str) -> Result<(), String> {
+    let mut rest = markup;
+    while let Some(start) = rest.find('<') {
+        let (name, attributes, len) = try!(parse_tag(&rest[start + 1..]));
+        if !ALLOWED_TAGS.contains(&name.as_str()) {
+            return Err(format!("tag <{}> is not allowed", name));
+        }
+        for (key, value) in attributes {
+            match (name.as_str(), key.as_str()) {
+                ("a", "href") if has_safe_scheme(value, &["http", "https", "mailto"]) => (),
+                ("img", "src") if has_safe_scheme(value, &["http", "https"]) => (),
+                ("img", "alt") | ("img", "width") | ("img", "height") => (),
+                _ => return Err(format!("attribute {:?} is not allowed on <{}>", key, name))
+            }
+        }
+        rest = &rest[start + 1 + len..];
+    }
+    Ok(())
+}
+
+/// A tag's lowercased name, its attributes and its length up to and including the closing `>`
+type ParsedTag<'a> = (String, Vec<(String, &'a str)>, usize);
+
+/// Parses a tag following its `<`. Quoted attribute values may contain `>`,
+/// so the tag ends at the first `>` outside quotes
+fn parse_tag(tag: &str) -> Result<ParsedTag<'_>, String> {
+    let is_delimiter = |c: char| c.is_whitespace() || c == '/' || c == '>' || c == '=';
+    let mut i = if tag.starts_with('/') { 1 } else { 0 };
+    let name_len = tag[i..].find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(tag.len() - i);
+    if name_len == 0 {
+        return Err("invalid tag".to_owned());
+    }
+    let name = tag[i..i + name_len].to_lowercase();
+    i += name_len;
+
+    let mut attributes = Vec::new();
+    loop {
+        i += tag[i..].len() - tag[i..].trim_start_matches(|c: char| c.is_whitespace() || c == '/').len();
+        let c = match tag[i..].chars().next() {
+            Some(c) => c,
+            None => return Err("unterminated tag".to_owned())
+        };
+        if c == '>' {
+            return Ok((name, attributes, i + 1));
+        }
+
+        let key_len = tag[i..].find(is_delimiter).unwrap_or(tag.len() - i);
+        let key = &tag[i..i + key_len];
+        if key.contains(['"', '\'', '<']) {
+            return Err(format!("invalid attribute {:?} on <{}>", key, name));
+        }
+        i += key_len;
+
+        let after_key = tag[i..].trim_start();
+        if !after_key.starts_with('=') {
+            attributes.push((key.to_lowercase(), ""));
+            continue;
+        }
+        i = tag.len() - after_key[1..].trim_start().len();
+        let value = match tag[i..].chars().next() {
+            Some(quote) if quote == '"' || quote == '\'' => {
+                let len = match tag[i + 1..].find(quote) {
+                    Some(len) => len,
+                    None => return Err("unterminated attribute value".to_owned())
+                };
+                let value = &tag[i + 1..i + 1 + len];
+                i += len + 2;
+                value
+            }
+            _ => {
+                let len = tag[i..].find(|c: char| c.is_whitespace() || c == '>').unwrap_or(tag.len() - i);
+                let value = &tag[i..i + len];
+                if value.contains(['"', '\'', '<', '=', '`']) {
+                    return Err(format!("invalid value for attribute {:?} on <{}>", key, name));
+                }
+                i += len;
+                value
+            }
+        };
+        attributes.push((key.to_lowercase(), value));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unit_escape() {
+        assert_eq!(escape(r#"<a href="x">Tom & 'Jerry'</a>"#), "&lt;a href=&quot;x&quot;&gt;Tom &amp; &#39;Jerry&#39;&lt;/a&gt;");
+    }
+
+    #[test]
+    fn unit_html_builder_nests_and_escapes() {
+        let actual = Html::new()
+            .bold(Html::new().italic("1 < 2"))
+            .line_break()
+            .list(vec!["a&b", "c"])
+            .ordered_list(vec![Html::new().code("x")])
+            .pre("fn main() {}")
+            .image("https://example.com/a.png", "\"chart\"")
+            .build()
+            .unwrap();
+        assert_eq!(actual, "<b><i>1 &lt; 2</i></b><br><ul><li>a&amp;b</li><li>c</li></ul><ol><li><code>x</code></li></ol>\
+                            <pre>fn main() {}</pre><img src=\"https://example.com/a.png\" alt=\"&quot;chart&quot;\">");
+    }
+
+    #[test]
+    fn unit_html_table() {
+        let actual = Html::new()
+            .table(&["Build", "Status"], vec![vec!["#42", "<failed>"]])
+            .build()
+            .unwrap();
+        assert_eq!(actual, "<table><tr><td><b>Build</b></td><td><b>Status</b></td></tr><tr><td>#42</td><td>&lt;failed&gt;</td></tr></table>");
+    }
+
+    #[test]
+    fn unit_html_rejects_unsafe_urls() {
+        assert!(Html::new().link("javascript:alert(1)", "x").build().is_err());
+        assert!(Html::new().image("data:image/png;base64,AAAA", "x").build().is_err());
+        assert!(Html::new().link("mailto:ops@example.com", "mail").build().is_ok());
+    }
+
+    #[test]
+    fn unit_html_enforces_length_limit() {
+        let text = "a".repeat(NOTIFICATION_MAX_LENGTH);
+        assert!(Html::new().text(text.as_str()).build().is_ok());
+        assert!(Html::new().bold(text.as_str()).build().is_err());
+        assert!(Html::new().build().is_err());
+    }
+
+    #[test]
+    fn unit_check_markup() {
+        assert!(check_markup("<b>ok</b><br/><a href=\"https://example.com\">x</a>").is_ok());
+        assert!(check_markup("<script>alert(1)</script>").is_err());
+        assert!(check_markup("<a href=\"javascript:alert(1)\">x</a>").is_err());
+        assert!(check_markup("<img src=\"https://example.com/a.png\" onerror=\"alert(1)\">").is_err());
+        assert!(Html::raw("<div>x</div>").build().is_err());
+        assert!(check_markup("<img src='https://example.com/a.png' alt=\"a > b\" width=10>").is_ok());
+        assert!(check_markup("1 < 2").is_err());
+    }
+
+    #[test]
+    fn unit_check_markup_ignores_tag_end_inside_quotes() {
+        assert!(check_markup(r#"<img src="https://a/>" onerror="alert(1)">"#).is_err());
+        assert!(Html::raw(r#"<a href="https://x/>" onclick="alert(1)">x</a>"#).build().is_err());
+        assert!(check_markup(r#"<a href="https://x/>"#).is_err());
+        assert!(check_markup(r#"<a href=https://x/"onclick="alert(1)">x</a>"#).is_err());
+    }
+}
@@ -0,0 +1,4 @@
+//! Helpers for composing message bodies in each `MessageFormat`
+
+pub mod html;
+pub mod text;
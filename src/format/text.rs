@@ -0,0 +1,224 @@
+//! Composition of `MessageFormat::Text` messages with mentions and emoticons
+//!
+//! `Text` keeps `@mentions` apart from surrounding words so HipChat recognises
+//! them, and splits long bodies into chunks that fit `send_message`.
+//!
+//! ```
+//! use hipchat_client::format::text::Text;
+//!
+//! let chunks = Text::new()
+//!     .text("deploy finished, ")
+//!     .mention_here()
+//!     .text("please check")
+//!     .build()
+//!     .unwrap();
+//! assert_eq!(chunks, vec!["deploy finished, @here please check"]);
+//! ```
+
+use client::Client;
+use emoticon::Emoticon;
+use error::Error;
+use message::SendMessageResponse;
+use room::MESSAGE_MAX_LENGTH;
+use user::{User, UserDetail};
+
+/// Anything that can be `@mentioned`
+pub trait Mentionable {
+    fn mention_name(&self) -> &str;
+}
+
+impl Mentionable for User {
+    fn mention_name(&self) -> &str {
+        &self.mention_name
+    }
+}
+
+impl Mentionable for UserDetail {
+    fn mention_name(&self) -> &str {
+        &self.mention_name
+    }
+}
+
+impl<M: Mentionable> Mentionable for &M {
+    fn mention_name(&self) -> &str {
+        (*self).mention_name()
+    }
+}
+
+/// A plain text message body
+#[derive(Debug, Clone, Default, Hash, Eq, PartialEq)]
+pub struct Text {
+    body: String,
+    after_token: bool
+}
+
+impl Text {
+    pub fn new() -> Self {
+        Text::default()
+    }
+    /// Appends text as is
+    pub fn text<T: AsRef<str>>(mut self, text: T) -> Self {
+        let text = text.as_ref();
+        if self.after_token && text.starts_with(is_word_char) {
+            self.body.push(' ');
+        }
+        self.body.push_str(text);
+        self.after_token = false;
+        self
+    }
+    pub fn line<T: AsRef<str>>(self, text: T) -> Self {
+        self.text(text).text("\n")
+    }
+    pub fn mention<M: Mentionable>(self, user: M) -> Self {
+        self.token(&format!("@{}", user.mention_name().trim_start_matches('@')))
+    }
+    /// Mentions everyone in the room
+    pub fn mention_all(self) -> Self {
+        self.token("@all")
+    }
+    /// Mentions everyone in the room who is available
+    pub fn mention_here(self) -> Self {
+        self.token("@here")
+    }
+    pub fn emoticon(self, emoticon: &Emoticon) -> Self {
+        self.token(&format!("({})", emoticon.shortcut))
+    }
+    /// The body split into chunks of at most `MESSAGE_MAX_LENGTH` characters
+    ///
+    /// Chunks end at line breaks where possible, then at spaces, so a line
+    /// is only cut mid-word if it has no space to break at.
+    pub fn build(self) -> Result<Vec<String>, Error> {
+        if self.body.trim().is_empty() {
+            return Err(Error::Validation("message must not be empty".to_owned()));
+        }
+        Ok(split(&self.body, MESSAGE_MAX_LENGTH))
+    }
+    /// Sends the message to a room, one `send_message` call per chunk
+    pub fn send<T: AsRef<str>>(self, client: &Client, room_id_or_name: T) -> Result<Vec<SendMessageResponse>, Error> {
+        let mut responses = Vec::new();
+        for chunk in try!(self.build()) {
+            responses.push(try!(client.send_message(room_id_or_name.as_ref(), chunk)));
+        }
+        Ok(responses)
+    }
+    fn token(mut self, token: &str) -> Self {
+        if self.body.ends_with(is_word_char) || self.after_token {
+            self.body.push(' ');
+        }
+        self.body.push_str(token);
+        self.after_token = true;
+        self
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '@' || c == ')'
+}
+
+/// Splits `body` into non-empty chunks of at most `max` characters
+fn split(body: &str, max: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut current_len = 0;
+
+    for line in body.trim_end_matches('\n').split('\n') {
+        for piece in split_line(line, max) {
+            let len = piece.chars().count();
+            if current_len > 0 && current_len + 1 + len > max {
+                chunks.push(current);
+                current = String::new();
+                current_len = 0;
+            }
+            if current_len > 0 {
+                current.push('\n');
+                current_len += 1;
+            }
+            current.push_str(piece);
+            current_len += len;
+        }
+    }
+    if !current.trim().is_empty() {
+        chunks.push(current);
+    }
+    chunks.retain(|chunk| !chunk.trim().is_empty());
+    chunks
+}
+
+/// Cuts a single line into pieces of at most `max` characters, preferring spaces
+fn split_line(mut line: &str, max: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    while line.chars().count() > max {
+        let limit = line.char_indices().nth(max).map(|(i, _)| i).unwrap_or(line.len());
+        let cut = match line[..limit].rfind(' ') {
+            _ if line[limit..].starts_with(' ') => limit,
+            Some(i) if i > 0 => i,
+            _ => limit
+        };
+        pieces.push(&line[..cut]);
+        line = line[cut..].trim_start_matches(' ');
+    }
+    pieces.push(line);
+    pieces
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::{self};
+    use test_server::{self, TestServer};
+
+    fn user(mention_name: &str) -> User {
+        serde_json::from_str(&format!(r#"{{
+            "name": "{0}", "mention_name": "{0}", "id": 1,
+            "links": {{ "self": "https://api.hipchat.com/v2/user/1" }}
+        }}"#, mention_name)).unwrap()
+    }
+
+    #[test]
+    fn unit_text_separates_tokens() {
+        let emoticon: Emoticon = serde_json::from_str(r#"{
+            "width": 30, "audio_path": null, "id": 1, "shortcut": "success", "height": "30"
+        }"#).unwrap();
+        let bob = user("@bob");
+        let actual = Text::new()
+            .text("cc")
+            .mention(user("alice"))
+            .mention(&bob)
+            .text(": build passed")
+            .emoticon(&emoticon)
+            .text("thanks")
+            .build()
+            .unwrap();
+        assert_eq!(actual, vec!["cc @alice @bob: build passed (success) thanks"]);
+        assert_eq!(bob.mention_name(), "@bob");
+    }
+
+    #[test]
+    fn unit_text_rejects_empty_body() {
+        assert!(Text::new().build().is_err());
+        assert!(Text::new().line("").build().is_err());
+    }
+
+    #[test]
+    fn unit_split_keeps_line_boundaries() {
+        assert_eq!(split("aaa\nbbb\ncc", 8), vec!["aaa\nbbb", "cc"]);
+        assert_eq!(split("one two three\nfour", 10), vec!["one two", "three\nfour"]);
+        assert_eq!(split("abcdefghij", 4), vec!["abcd", "efgh", "ij"]);
+        assert_eq!(split("ééééé", 2), vec!["éé", "éé", "é"]);
+    }
+
+    #[test]
+    fn unit_text_send_chunks_long_messages() {
+        let response = test_server::json(r#"{"id": "1", "timestamp": "2017-03-20T12:00:00.000000+00:00"}"#);
+        let server = TestServer::new(vec![response.clone(), response]);
+        let client = test_server::client(&server);
+
+        let line = "a".repeat(600);
+        let responses = Text::new().line(&line).line(&line).send(&client, "Ops").unwrap();
+        assert_eq!(responses.len(), 2);
+
+        let request = server.requests.recv().unwrap();
+        assert_eq!(request.path, "/v2/room/Ops/message");
+        assert_eq!(request.body, format!(r#"{{"message":"{}"}}"#, line));
+    }
+}
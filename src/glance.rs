@@ -0,0 +1,142 @@
+//! [Glances](https://developer.atlassian.com/hipchat/guide/glances), the status lines add-ons show in the HipChat sidebar
+
+use serde_json::Value;
+
+use error::Error;
+use format::html::escape;
+use util::validate_length;
+
+/// Maximum length of a glance key
+pub const GLANCE_KEY_MAX_LENGTH: usize = 40;
+
+/// The text shown next to a glance's icon
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub enum GlanceLabel {
+    Html(String)
+}
+
+impl GlanceLabel {
+    /// A label showing `text` as is, escaped for HTML
+    pub fn text(text: &str) -> Self {
+        GlanceLabel::Html(escape(text))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LozengeType {
+    Default,
+    Success,
+    Error,
+    Current,
+    Complete,
+    Moved,
+    New
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Lozenge {
+    pub label: String,
+    #[serde(rename = "type")]
+    pub lozenge_type: LozengeType
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct GlanceIcon {
+    pub url: String,
+    #[serde(rename = "url@2x")]
+    pub url_2x: String
+}
+
+/// The lozenge or icon shown at the right of a glance
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub enum GlanceStatus {
+    Lozenge(Lozenge),
+    Icon(GlanceIcon)
+}
+
+impl GlanceStatus {
+    pub fn lozenge<T: Into<String>>(label: T, lozenge_type: LozengeType) -> Self {
+        GlanceStatus::Lozenge(Lozenge { label: label.into(), lozenge_type: lozenge_type })
+    }
+    pub fn icon<T: Into<String>, U: Into<String>>(url: T, url_2x: U) -> Self {
+        GlanceStatus::Icon(GlanceIcon { url: url.into(), url_2x: url_2x.into() })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GlanceContent {
+    pub label: GlanceLabel,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<GlanceStatus>,
+    /// Values matched against the conditions in the add-on descriptor
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Value>
+}
+
+/// New content for the glance with the given key, as declared in the add-on descriptor
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GlanceUpdate {
+    pub key: String,
+    pub content: GlanceContent
+}
+
+impl GlanceUpdate {
+    pub fn new<T: Into<String>>(key: T, label: GlanceLabel) -> Self {
+        GlanceUpdate {
+            key: key.into(),
+            content: GlanceContent { label: label, status: None, metadata: None }
+        }
+    }
+    pub fn status(mut self, status: GlanceStatus) -> Self {
+        self.content.status = Some(status);
+        self
+    }
+    pub fn metadata(mut self, metadata: Value) -> Self {
+        self.content.metadata = Some(metadata);
+        self
+    }
+    pub fn validate(&self) -> Result<(), Error> {
+        validate_length("key", &self.key, 1, GLANCE_KEY_MAX_LENGTH)
+    }
+}
+
+/// Request body for the glance update endpoints
+#[derive(Debug, Serialize)]
+pub struct GlanceUpdates<'a> {
+    pub glance: &'a [GlanceUpdate]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::{self};
+
+    #[test]
+    fn unit_serialize_glance_update() {
+        let mut metadata = ::serde_json::Map::new();
+        metadata.insert("failing".to_owned(), Value::Bool(true));
+        let update = GlanceUpdate::new("build-health", GlanceLabel::text("2 < 3 builds"))
+            .status(GlanceStatus::lozenge("FAILING", LozengeType::Error))
+            .metadata(Value::Object(metadata));
+        let actual = serde_json::to_string(&GlanceUpdates { glance: &[update] }).unwrap();
+        let expected = r#"{"glance":[{"key":"build-health","content":{"label":{"type":"html","value":"2 &lt; 3 builds"},"status":{"type":"lozenge","value":{"label":"FAILING","type":"error"}},"metadata":{"failing":true}}}]}"#;
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn unit_serialize_glance_icon() {
+        let actual = serde_json::to_string(&GlanceStatus::icon("https://example.com/a.png", "https://example.com/a@2x.png")).unwrap();
+        let expected = r#"{"type":"icon","value":{"url":"https://example.com/a.png","url@2x":"https://example.com/a@2x.png"}}"#;
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn unit_validate_glance_key() {
+        assert!(GlanceUpdate::new("", GlanceLabel::text("x")).validate().is_err());
+        let key = "k".repeat(GLANCE_KEY_MAX_LENGTH + 1);
+        assert!(GlanceUpdate::new(key, GlanceLabel::text("x")).validate().is_err());
+    }
+}
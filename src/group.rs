@@ -0,0 +1,70 @@
+//! Groups, the HipChat accounts that own users and rooms
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Group {
+    pub id: u64,
+    pub name: String,
+    pub subdomain: Option<String>,
+    pub domain: Option<String>,
+    pub avatar_url: Option<String>,
+    pub plan: Option<GroupPlan>,
+    pub owner: Option<GroupOwner>,
+    pub links: GroupLinks
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct GroupPlan {
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub plan_type: Option<String>
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct GroupOwner {
+    pub id: u64,
+    pub name: String,
+    pub mention_name: String,
+    pub links: GroupLinks
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct GroupLinks {
+    #[serde(rename = "self")]
+    pub self_: String
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct GroupStatistics {
+    /// Number of active users in the group
+    pub users: Option<u64>,
+    pub links: Option<GroupLinks>
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::{self};
+
+    #[test]
+    fn unit_deserialize_group() {
+        let group: Group = serde_json::from_str(r#"{
+            "id": 42,
+            "name": "Example",
+            "subdomain": "example",
+            "domain": null,
+            "avatar_url": "https://example.com/avatar.png",
+            "plan": {"id": "premium", "name": "Plus", "type": "paid"},
+            "owner": {"id": 1, "name": "Ada", "mention_name": "ada", "links": {"self": "https://api.hipchat.com/v2/user/1"}},
+            "links": {"self": "https://api.hipchat.com/v2/group/42"}
+        }"#).unwrap();
+        assert_eq!(group.plan.unwrap().plan_type, Some("paid".to_owned()));
+        assert_eq!(group.owner.unwrap().mention_name, "ada");
+    }
+
+    #[test]
+    fn unit_deserialize_group_statistics() {
+        let statistics: GroupStatistics = serde_json::from_str(r#"{"users": 58, "links": {"self": "https://api.hipchat.com/v2/group/42/statistics"}}"#).unwrap();
+        assert_eq!(statistics.users, Some(58));
+    }
+}
@@ -1,18 +1,47 @@
+extern crate base64;
+extern crate hmac;
 extern crate hyper;
+#[cfg(feature = "native-tls")]
 extern crate hyper_native_tls;
+#[cfg(feature = "rustls")]
+extern crate hyper_sync_rustls;
+#[cfg(feature = "rustls")]
+extern crate rustls;
+#[cfg(feature = "rustls")]
+extern crate webpki_roots;
 #[macro_use]
 extern crate serde_derive;
 
 extern crate serde;
 extern crate serde_json;
+extern crate sha2;
 extern crate url;
 
+pub mod addon;
+pub mod auth;
+pub mod bot;
+pub mod cache;
+pub mod capabilities;
 pub mod client;
+pub mod conditional;
+pub mod connector;
 pub mod error;
+pub mod export;
+pub mod format;
+pub mod glance;
+pub mod group;
+pub mod lifecycle;
 pub mod emoticon;
 pub mod room;
+pub mod slack;
 pub mod user;
 pub mod message;
+pub mod parse;
+pub mod tls;
 pub mod util;
+pub mod watcher;
 
-pub use client::Client;
+#[cfg(test)]
+mod test_server;
+
+pub use client::{Client, ClientBuilder};
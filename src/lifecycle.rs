@@ -0,0 +1,239 @@
+//! Archiving and unarchiving rooms, one at a time or in bulk
+
+use std::ops::Deref;
+use std::thread;
+use std::time::Duration;
+
+use hyper::status::StatusCode;
+
+use client::Client;
+use error::Error;
+use room::{Room, RoomDetail, RoomStatistics, RoomUpdate, RoomsRequest};
+use util::MAX_RESULTS_LIMIT;
+
+/// Wait after HipChat reports that the rate limit was exceeded
+const RATE_LIMIT_BACKOFF: u64 = 60;
+const DEFAULT_MAX_RETRIES: u32 = 3;
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub struct RoomSummary {
+    pub id: u64,
+    pub name: String
+}
+
+/// The outcome of a bulk operation
+#[derive(Debug, Default)]
+pub struct LifecycleReport {
+    pub dry_run: bool,
+    /// Rooms that were changed, or would have been in a dry run
+    pub changed: Vec<RoomSummary>,
+    /// Rooms that matched but were already in the requested state
+    pub unchanged: Vec<RoomSummary>,
+    pub failed: Vec<(RoomSummary, Error)>
+}
+
+/// Archives and unarchives rooms
+///
+/// HipChat only archives through a full room update, so each change reads
+/// the room first and writes it back with `is_archived` flipped. Requests
+/// rejected with `429 Too Many Requests` are retried after a backoff.
+/// `C` is anything that derefs to a `Client`, such as `&Client` or `Arc<Client>`.
+pub struct RoomLifecycle<C: Deref<Target = Client>> {
+    client: C,
+    dry_run: bool,
+    delay: Duration,
+    rate_limit_backoff: Duration,
+    max_retries: u32
+}
+
+impl<C: Deref<Target = Client>> RoomLifecycle<C> {
+    pub fn new(client: C) -> Self {
+        RoomLifecycle {
+            client: client,
+            dry_run: false,
+            delay: Duration::from_secs(0),
+            rate_limit_backoff: Duration::from_secs(RATE_LIMIT_BACKOFF),
+            max_retries: DEFAULT_MAX_RETRIES
+        }
+    }
+    /// Reports what would change without updating any room
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+    /// Pause between room updates in bulk operations. Defaults to none
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+    /// Wait before retrying a rate limited request. Defaults to 60 seconds
+    pub fn rate_limit_backoff(mut self, backoff: Duration) -> Self {
+        self.rate_limit_backoff = backoff;
+        self
+    }
+    /// Retries per request after rate limiting. Defaults to 3
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+    /// Archives a room by id or name. Returns whether it was (or in a dry run, would be) changed
+    pub fn archive<T: AsRef<str>>(&self, room_id_or_name: T) -> Result<bool, Error> {
+        self.set_archived(room_id_or_name.as_ref(), true)
+    }
+    /// Unarchives a room by id or name. Returns whether it was (or in a dry run, would be) changed
+    pub fn unarchive<T: AsRef<str>>(&self, room_id_or_name: T) -> Result<bool, Error> {
+        self.set_archived(room_id_or_name.as_ref(), false)
+    }
+    /// Archives every active room for which `predicate` returns true, e.g. to match names against a pattern
+    pub fn archive_where<F: FnMut(&Room) -> bool>(&self, mut predicate: F) -> Result<LifecycleReport, Error> {
+        let rooms = try!(self.active_rooms());
+        Ok(self.archive_all(rooms.into_iter().filter(|room| predicate(room)).collect()))
+    }
+    /// Like `archive_where`, but also gives the predicate each room's statistics
+    pub fn archive_where_with_statistics<F>(&self, mut predicate: F) -> Result<LifecycleReport, Error>
+        where F: FnMut(&Room, &RoomStatistics) -> bool
+    {
+        let mut matching = Vec::new();
+        for room in try!(self.active_rooms()) {
+            let statistics = try!(self.retry(|| self.client.get_room_statistics(room.id.to_string())));
+            if predicate(&room, &statistics) {
+                matching.push(room);
+            }
+        }
+        Ok(self.archive_all(matching))
+    }
+    /// Archives rooms with no messages since `cutoff`, an ISO 8601 time in the
+    /// format HipChat uses for `last_active`. Rooms never used are included
+    pub fn archive_inactive_since(&self, cutoff: &str) -> Result<LifecycleReport, Error> {
+        self.archive_where_with_statistics(|_, statistics| {
+            statistics.last_active.as_ref().map(|last_active| last_active.as_str() < cutoff).unwrap_or(true)
+        })
+    }
+    fn archive_all(&self, rooms: Vec<Room>) -> LifecycleReport {
+        let mut report = LifecycleReport { dry_run: self.dry_run, ..LifecycleReport::default() };
+        for (i, room) in rooms.into_iter().enumerate() {
+            if i > 0 && !self.dry_run {
+                thread::sleep(self.delay);
+            }
+            let summary = RoomSummary { id: room.id, name: room.name };
+            match self.set_archived(&summary.id.to_string(), true) {
+                Ok(true) => report.changed.push(summary),
+                Ok(false) => report.unchanged.push(summary),
+                Err(e) => report.failed.push((summary, e))
+            }
+        }
+        report
+    }
+    fn set_archived(&self, room_id_or_name: &str, archived: bool) -> Result<bool, Error> {
+        let room = try!(self.retry(|| self.client.get_room(room_id_or_name)));
+        if room.is_archived == archived {
+            return Ok(false);
+        }
+        if !self.dry_run {
+            let update = try!(archive_update(&room, archived));
+            try!(self.retry(|| self.client.update_room(room.id.to_string(), &update)));
+        }
+        Ok(true)
+    }
+    fn active_rooms(&self) -> Result<Vec<Room>, Error> {
+        let mut rooms = Vec::new();
+        let mut start_index = 0;
+        loop {
+            let req = RoomsRequest {
+                start_index: Some(start_index),
+                max_results: Some(MAX_RESULTS_LIMIT),
+                include_private: Some(true),
+                include_archived: Some(false)
+            };
+            let page = try!(self.retry(|| self.client.get_rooms(Some(&req))));
+            let count = page.items.len() as u64;
+            rooms.extend(page.items);
+            if page.links.next.is_none() || count == 0 {
+                return Ok(rooms);
+            }
+            start_index += count;
+        }
+    }
+    fn retry<T, F: FnMut() -> Result<T, Error>>(&self, mut f: F) -> Result<T, Error> {
+        let mut retries = 0;
+        loop {
+            match f() {
+                Err(Error::HttpStatus(StatusCode::TooManyRequests)) if retries < self.max_retries => {
+                    retries += 1;
+                    thread::sleep(self.rate_limit_backoff);
+                }
+                result => return result
+            }
+        }
+    }
+}
+
+/// A full room update that keeps the room's settings and changes `is_archived`
+fn archive_update(room: &RoomDetail, archived: bool) -> Result<RoomUpdate, Error> {
+    RoomUpdate::from_patched_room(room, &RoomUpdate { is_archived: Some(archived), ..RoomUpdate::default() })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_server::{self, TestServer};
+
+    fn rooms(rooms: &[(u64, &str)]) -> String {
+        let items: Vec<String> = rooms.iter().map(|&(id, name)| format!(r#"{{
+            "id": {0}, "name": "{1}",
+            "links": {{"self": "https://api.hipchat.com/v2/room/{0}", "webhooks": "w", "participants": "p"}}
+        }}"#, id, name)).collect();
+        test_server::json(&format!(r#"{{"startIndex": 0, "maxResults": 1000, "items": [{}], "links": {{"self": "https://api.hipchat.com/v2/room"}}}}"#, items.join(",")))
+    }
+
+    fn room(id: u64, name: &str, archived: bool) -> String {
+        test_server::json(&test_server::room(id, name, archived))
+    }
+
+    #[test]
+    fn unit_archive_where_updates_matching_rooms() {
+        let server = TestServer::new(vec![
+            rooms(&[(1, "tmp-incident"), (2, "ops")]),
+            room(1, "tmp-incident", false),
+            test_server::response("429 Too Many Requests", &[], ""),
+            test_server::response("204 No Content", &[], "")
+        ]);
+        let client = test_server::client(&server);
+        let lifecycle = RoomLifecycle::new(&client).rate_limit_backoff(Duration::from_secs(0));
+
+        let report = lifecycle.archive_where(|room| room.name.starts_with("tmp-")).unwrap();
+        assert_eq!(report.changed, vec![RoomSummary { id: 1, name: "tmp-incident".to_owned() }]);
+        assert!(report.failed.is_empty());
+
+        assert_eq!(server.requests.recv().unwrap().path, "/v2/room?start-index=0&max-results=1000&include-private=true&include-archived=false");
+        server.requests.recv().unwrap();
+        server.requests.recv().unwrap();
+        let update = server.requests.recv().unwrap();
+        assert_eq!(update.method, "PUT");
+        assert_eq!(update.body, r#"{"name":"tmp-incident","privacy":"public","is_archived":true,"is_guest_accessible":false,"topic":"t","owner":{"id":"5"}}"#);
+    }
+
+    #[test]
+    fn unit_archive_inactive_dry_run() {
+        let server = TestServer::new(vec![
+            rooms(&[(1, "old"), (2, "busy")]),
+            test_server::json(r#"{"messages_sent": 3, "last_active": "2016-01-01T00:00:00+00:00"}"#),
+            test_server::json(r#"{"messages_sent": 900, "last_active": "2017-03-01T00:00:00+00:00"}"#),
+            room(1, "old", false)
+        ]);
+        let client = test_server::client(&server);
+        let report = RoomLifecycle::new(&client).dry_run(true).archive_inactive_since("2017-01-01T00:00:00+00:00").unwrap();
+        assert!(report.dry_run);
+        assert_eq!(report.changed, vec![RoomSummary { id: 1, name: "old".to_owned() }]);
+        for _ in 0..4 {
+            assert_eq!(server.requests.recv().unwrap().method, "GET");
+        }
+    }
+
+    #[test]
+    fn unit_unarchive_skips_active_room() {
+        let server = TestServer::new(vec![room(2, "ops", false)]);
+        let client = test_server::client(&server);
+        assert!(!RoomLifecycle::new(&client).unarchive("ops").unwrap());
+    }
+}
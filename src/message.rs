@@ -1,6 +1,7 @@
-use user::{UserDetail};
+use user::{User, UserDetail};
 
-use util::AppendToQueryParams;
+use error::Error;
+use util::{AppendToQueryParams, validate_max_results};
 use url::UrlQuery;
 use url::form_urlencoded::Serializer;
 
@@ -44,7 +45,7 @@ impl Default for MessageFormat {
     }
 }
 
-#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct MessagesRequest {
     pub start_index: Option<u64>,
     pub max_results: Option<u64>,
@@ -55,6 +56,55 @@ pub struct MessagesRequest {
     pub end_date: Option<String>,
 }
 
+impl MessagesRequest {
+    pub fn builder() -> MessagesRequestBuilder {
+        MessagesRequestBuilder::default()
+    }
+    pub fn validate(&self) -> Result<(), Error> {
+        validate_max_results(self.max_results)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MessagesRequestBuilder {
+    request: MessagesRequest
+}
+
+impl MessagesRequestBuilder {
+    pub fn start_index(mut self, start_index: u64) -> Self {
+        self.request.start_index = Some(start_index);
+        self
+    }
+    pub fn max_results(mut self, max_results: u64) -> Self {
+        self.request.max_results = Some(max_results);
+        self
+    }
+    pub fn reversed(mut self, reversed: bool) -> Self {
+        self.request.reversed = Some(reversed);
+        self
+    }
+    pub fn date<T: Into<String>>(mut self, date: T) -> Self {
+        self.request.date = Some(date.into());
+        self
+    }
+    pub fn include_deleted(mut self, include_deleted: bool) -> Self {
+        self.request.include_deleted = Some(include_deleted);
+        self
+    }
+    pub fn timezone<T: Into<String>>(mut self, timezone: T) -> Self {
+        self.request.timezone = Some(timezone.into());
+        self
+    }
+    pub fn end_date<T: Into<String>>(mut self, end_date: T) -> Self {
+        self.request.end_date = Some(end_date.into());
+        self
+    }
+    pub fn build(self) -> Result<MessagesRequest, Error> {
+        try!(self.request.validate());
+        Ok(self.request)
+    }
+}
+
 impl AppendToQueryParams for MessagesRequest {
     fn append_to(&self, query: &mut Serializer<UrlQuery>){
         self.start_index.map(|start_index| query.append_pair("start-index", &start_index.to_string()));
@@ -67,13 +117,38 @@ impl AppendToQueryParams for MessagesRequest {
     }
 }
 
-#[derive(Debug, Clone, Hash, Eq, PartialEq, Deserialize)]
+/// Query parameters for [View recent room history](https://www.hipchat.com/docs/apiv2/method/view_recent_room_history)
+#[derive(Debug, Default, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RecentHistoryRequest {
+    pub max_results: Option<u64>,
+    pub timezone: Option<String>,
+    /// Id of the oldest message to return
+    pub not_before: Option<String>,
+    pub include_deleted: Option<bool>
+}
+
+impl RecentHistoryRequest {
+    pub fn validate(&self) -> Result<(), Error> {
+        validate_max_results(self.max_results)
+    }
+}
+
+impl AppendToQueryParams for RecentHistoryRequest {
+    fn append_to(&self, query: &mut Serializer<UrlQuery>){
+        self.max_results.map(|max_results| query.append_pair("max-results", &max_results.to_string()));
+        self.timezone.as_ref().map(|timezone| query.append_pair("timezone", timezone));
+        self.not_before.as_ref().map(|not_before| query.append_pair("not-before", not_before));
+        self.include_deleted.map(|include_deleted| query.append_pair("include_deleted", &include_deleted.to_string()));
+    }
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct MessageDetailLinks {
     #[serde(rename = "self")]
     pub self_: String,
 }
 
-#[derive(Debug, Clone, Hash, Eq, PartialEq, Deserialize)]
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Messages {
     #[serde(skip_deserializing)]
     pub start_index: u64,
@@ -83,7 +158,7 @@ pub struct Messages {
     pub links: MessageDetailLinks
 }
 
-#[derive(Debug, Clone, Hash, Eq, PartialEq, Deserialize)]
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct MessageFile {
     pub url: String,
     pub thumb_url: Option<String>,
@@ -91,26 +166,101 @@ pub struct MessageFile {
     pub size: u64
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SendMessageResponse {
     pub id: String,
     pub timestamp: String
 }
 
-#[derive(Debug, Clone, Hash, Eq, PartialEq, Deserialize)]
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(from = "MessageDocument", into = "MessageDocument")]
 pub struct Message {
     pub id: String,
     pub date: String,
+    /// The sender, or `None` for notifications, which HipChat sends with only the sender's name
     pub from: Option<UserDetail>,
+    /// The sender's name for notifications, such as the integration that sent them
+    pub from_name: Option<String>,
     pub message: String,
     pub message_format: Option<MessageFormat>,
-    #[serde(rename(deserialize="type"))]
     pub message_type: MessageType,
     pub color: Option<Color>,
-    pub mentions: Vec<String>,
+    pub mentions: Vec<User>,
     pub file: Option<MessageFile>,
 }
 
+impl Message {
+    /// The sender's mention name, or the name a notification was sent under
+    pub fn sender_name(&self) -> Option<&str> {
+        match self.from {
+            Some(ref from) => Some(from.mention_name.as_str()),
+            None => self.from_name.as_deref()
+        }
+    }
+}
+
+/// A message as HipChat sends it, where `from` is a user or, for notifications, a name
+#[derive(Serialize, Deserialize)]
+struct MessageDocument {
+    id: String,
+    date: String,
+    #[serde(default)]
+    from: Option<Sender>,
+    message: String,
+    message_format: Option<MessageFormat>,
+    #[serde(rename = "type")]
+    message_type: MessageType,
+    color: Option<Color>,
+    #[serde(default)]
+    mentions: Vec<User>,
+    file: Option<MessageFile>
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Sender {
+    User(Box<UserDetail>),
+    Name(String)
+}
+
+impl From<MessageDocument> for Message {
+    fn from(document: MessageDocument) -> Message {
+        let (from, from_name) = match document.from {
+            Some(Sender::User(user)) => (Some(*user), None),
+            Some(Sender::Name(name)) => (None, Some(name)),
+            None => (None, None)
+        };
+        Message {
+            id: document.id,
+            date: document.date,
+            from: from,
+            from_name: from_name,
+            message: document.message,
+            message_format: document.message_format,
+            message_type: document.message_type,
+            color: document.color,
+            mentions: document.mentions,
+            file: document.file
+        }
+    }
+}
+
+impl From<Message> for MessageDocument {
+    fn from(message: Message) -> MessageDocument {
+        MessageDocument {
+            id: message.id,
+            date: message.date,
+            from: message.from.map(|user| Sender::User(Box::new(user))).or(message.from_name.map(Sender::Name)),
+            message: message.message,
+            message_format: message.message_format,
+            message_type: message.message_type,
+            color: message.color,
+            mentions: message.mentions,
+            file: message.file
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -174,4 +324,86 @@ mod test {
 
         assert_eq!(Some("start-index=1&max-results=10&reversed=false&date=2017-03-20T12%3A00%3A00%2B4%3A00&include-deleted=false&timezone=UTC&end-date=2017-03-20T13%3A00%3A00%2B4%3A00"), url.query());
     }
+
+    #[test]
+    fn unit_populated_recent_history_request_should_create_encoded_params(){
+        let recent_history_request = RecentHistoryRequest{ max_results: Some(100),
+                                                           timezone: None,
+                                                           not_before: Some("f1b4d2c8".to_string()),
+                                                           include_deleted: Some(true) };
+
+        let mut url = Url::parse("https://rsolomo.github.io/hipchat-client/hipchat_client/index.html").unwrap();
+
+        recent_history_request.append_to(&mut url.query_pairs_mut());
+
+        assert_eq!(Some("max-results=100&not-before=f1b4d2c8&include_deleted=true"), url.query());
+    }
+
+    #[test]
+    fn unit_messages_request_builder() {
+        let actual = MessagesRequest::builder()
+            .max_results(50)
+            .reversed(true)
+            .date("recent")
+            .build()
+            .unwrap();
+        let expected = MessagesRequest {
+            max_results: Some(50),
+            reversed: Some(true),
+            date: Some("recent".to_owned()),
+            ..MessagesRequest::default()
+        };
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn unit_messages_request_builder_rejects_max_results() {
+        assert!(MessagesRequest::builder().max_results(1001).build().is_err());
+    }
+
+    #[test]
+    fn unit_deserialize_messages_fixture() {
+        let messages: Messages = serde_json::from_str(r#"{
+            "items": [
+                {
+                    "date": "2017-03-01T16:35:31.123456+00:00",
+                    "from": {"id": 3744112, "links": {"self": "https://api.hipchat.com/v2/user/3744112"}, "mention_name": "ada", "name": "Ada Lovelace", "version": "6A6C2B1D"},
+                    "id": "b4e5b1c8-3f4a-4c3b-9a51-5c0b8c0c2b6e",
+                    "mentions": [{"id": 3744113, "links": {"self": "https://api.hipchat.com/v2/user/3744113"}, "mention_name": "grace", "name": "Grace Hopper", "version": "00000000"}],
+                    "message": "@grace deploying now",
+                    "type": "message"
+                },
+                {
+                    "color": "green",
+                    "date": "2017-03-01T16:36:02.000000+00:00",
+                    "from": "CI",
+                    "id": "0f6b1e26-2c4d-4d8c-a3f5-7a1b2c3d4e5f",
+                    "message": "Build <b>#42</b> passed",
+                    "message_format": "html",
+                    "type": "notification"
+                }
+            ],
+            "links": {"self": "https://api.hipchat.com/v2/room/2883426/history"},
+            "maxResults": 100,
+            "startIndex": 0
+        }"#).unwrap();
+        assert_eq!(messages.items[0].from.as_ref().unwrap().mention_name, "ada");
+        assert_eq!(messages.items[0].mentions[0].mention_name, "grace");
+        assert!(messages.items[1].from.is_none());
+        assert_eq!(messages.items[1].from_name, Some("CI".to_owned()));
+        assert_eq!(messages.items[1].sender_name(), Some("CI"));
+        assert!(messages.items[1].mentions.is_empty());
+        assert_eq!(messages.items[1].color, Some(Color::Green));
+    }
+
+    #[test]
+    fn unit_message_sender_name_round_trip() {
+        let json = r#"{"id":"1","date":"2017-03-01T16:36:02.000000+00:00","from":"CI","message":"passed","message_format":null,"type":"notification","color":null,"mentions":[],"file":null}"#;
+        let message: Message = serde_json::from_str(json).unwrap();
+        assert_eq!(serde_json::to_string(&message).unwrap(), json);
+
+        let anonymous: Message = serde_json::from_str(&json.replace(r#""CI""#, "null")).unwrap();
+        assert_eq!((anonymous.from, anonymous.from_name), (None, None));
+        assert!(serde_json::from_str::<Message>(&json.replace(r#""CI""#, "42")).is_err());
+    }
 }
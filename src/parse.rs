@@ -0,0 +1,212 @@
+//! Splits message text into mentions, emoticons, commands, URLs and code
+//!
+//! ```
+//! use hipchat_client::parse::{MessageParser, Token};
+//!
+//! let parser = MessageParser::new();
+//! let tokens = parser.parse("@alice see https://example.com (thumbsup)");
+//! assert_eq!(tokens[0], Token::Mention { name: "alice", user: None });
+//! assert_eq!(tokens[2], Token::Url("https://example.com"));
+//! ```
+
+use emoticon::Emoticon;
+use message::Message;
+use user::User;
+
+/// Longest shortcut HipChat allows for a custom emoticon
+const MAX_SHORTCUT_LENGTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token<'a> {
+    Text(&'a str),
+    /// `@name`, with the matching user when one was supplied to the parser.
+    /// `@all` and `@here` never resolve to a user
+    Mention { name: &'a str, user: Option<&'a User> },
+    /// `(shortcut)`, with the matching emoticon when one was supplied to the parser
+    Emoticon { shortcut: &'a str, emoticon: Option<&'a Emoticon> },
+    /// A message starting with `/name`, other than `/code`
+    Command { name: &'a str, args: &'a str },
+    Url(&'a str),
+    /// A `/code` message or a block between triple backticks
+    Code(&'a str)
+}
+
+/// Tokenizes messages, resolving mentions and emoticons against known users and emoticons
+///
+/// When no emoticons are supplied, any `(word)` of up to 32 letters and digits
+/// is treated as an emoticon. Otherwise only known shortcuts are, so that
+/// ordinary text such as "option (a)" is left alone.
+#[derive(Debug, Clone, Default)]
+pub struct MessageParser<'a> {
+    users: &'a [User],
+    emoticons: Option<&'a [Emoticon]>
+}
+
+impl<'a> MessageParser<'a> {
+    pub fn new() -> Self {
+        MessageParser { users: &[], emoticons: None }
+    }
+    pub fn users(mut self, users: &'a [User]) -> Self {
+        self.users = users;
+        self
+    }
+    pub fn emoticons(mut self, emoticons: &'a [Emoticon]) -> Self {
+        self.emoticons = Some(emoticons);
+        self
+    }
+    pub fn parse_message(&self, message: &'a Message) -> Vec<Token<'a>> {
+        self.parse(&message.message)
+    }
+    pub fn parse(&self, text: &'a str) -> Vec<Token<'a>> {
+        let mut tokens = Vec::new();
+
+        if let Some(command) = text.trim().strip_prefix('/') {
+            let (name, args) = match command.find(char::is_whitespace) {
+                Some(i) => (&command[..i], command[i..].trim()),
+                None => (command, "")
+            };
+            if !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-') {
+                if name == "code" {
+                    tokens.push(Token::Code(args));
+                } else {
+                    tokens.push(Token::Command { name: name, args: args });
+                }
+                return tokens;
+            }
+        }
+
+        let mut text_start = 0;
+        let mut i = 0;
+        while i < text.len() {
+            let rest = &text[i..];
+            let at_boundary = text[..i].chars().next_back().map(|c| !c.is_alphanumeric() && c != '_').unwrap_or(true);
+            let matched = if let Some(code) = rest.strip_prefix("```") {
+                code.find("```").map(|end| (Token::Code(code[..end].trim_matches('\n')), end + 6))
+            } else if at_boundary && (rest.starts_with("http://") || rest.starts_with("https://")) {
+                let len = url_length(rest);
+                Some((Token::Url(&rest[..len]), len))
+            } else if at_boundary && rest.starts_with('@') {
+                self.mention(rest)
+            } else if rest.starts_with('(') {
+                self.emoticon(rest)
+            } else {
+                None
+            };
+
+            match matched {
+                Some((token, len)) => {
+                    if text_start < i {
+                        tokens.push(Token::Text(&text[text_start..i]));
+                    }
+                    tokens.push(token);
+                    i += len;
+                    text_start = i;
+                }
+                None => i += rest.chars().next().map_or(1, char::len_utf8)
+            }
+        }
+        if text_start < text.len() {
+            tokens.push(Token::Text(&text[text_start..]));
+        }
+        tokens
+    }
+    fn mention(&self, rest: &'a str) -> Option<(Token<'a>, usize)> {
+        let len = rest[1..].find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(rest.len() - 1);
+        if len == 0 {
+            return None;
+        }
+        let name = &rest[1..1 + len];
+        let user = self.users.iter().find(|user| user.mention_name.eq_ignore_ascii_case(name));
+        Some((Token::Mention { name: name, user: user }, len + 1))
+    }
+    fn emoticon(&self, rest: &'a str) -> Option<(Token<'a>, usize)> {
+        let end = match rest.find(')') {
+            Some(end) if end > 1 && end <= MAX_SHORTCUT_LENGTH + 1 => end,
+            _ => return None
+        };
+        let shortcut = &rest[1..end];
+        if !shortcut.chars().all(char::is_alphanumeric) {
+            return None;
+        }
+        match self.emoticons {
+            Some(emoticons) => emoticons.iter()
+                .find(|emoticon| emoticon.shortcut == shortcut)
+                .map(|emoticon| (Token::Emoticon { shortcut: shortcut, emoticon: Some(emoticon) }, end + 1)),
+            None => Some((Token::Emoticon { shortcut: shortcut, emoticon: None }, end + 1))
+        }
+    }
+}
+
+/// Length of the URL at the start of `text`, leaving off trailing punctuation
+fn url_length(text: &str) -> usize {
+    let end = text.find(char::is_whitespace).unwrap_or(text.len());
+    let url = text[..end].trim_end_matches(|c| ".,;:!?'\")]>".contains(c));
+    url.len()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::{self};
+
+    fn user(mention_name: &str) -> User {
+        serde_json::from_str(&format!(r#"{{
+            "name": "{0}", "mention_name": "{0}", "id": 1,
+            "links": {{ "self": "https://api.hipchat.com/v2/user/1" }}
+        }}"#, mention_name)).unwrap()
+    }
+
+    fn emoticon(shortcut: &str) -> Emoticon {
+        serde_json::from_str(&format!(r#"{{
+            "width": 30, "audio_path": null, "id": 1, "shortcut": "{}", "height": "30"
+        }}"#, shortcut)).unwrap()
+    }
+
+    #[test]
+    fn unit_parse_text_mentions_and_urls() {
+        let users = vec![user("Alice")];
+        let parser = MessageParser::new().users(&users);
+        let tokens = parser.parse("hey @alice, @all: see https://ci.example.com/42. thanks");
+        assert_eq!(tokens, vec![
+            Token::Text("hey "),
+            Token::Mention { name: "alice", user: Some(&users[0]) },
+            Token::Text(", "),
+            Token::Mention { name: "all", user: None },
+            Token::Text(": see "),
+            Token::Url("https://ci.example.com/42"),
+            Token::Text(". thanks")
+        ]);
+    }
+
+    #[test]
+    fn unit_parse_ignores_email_addresses() {
+        let tokens = MessageParser::new().parse("mail bob@example.com");
+        assert_eq!(tokens, vec![Token::Text("mail bob@example.com")]);
+    }
+
+    #[test]
+    fn unit_parse_emoticons() {
+        let emoticons = vec![emoticon("allthethings")];
+        let parser = MessageParser::new().emoticons(&emoticons);
+        let tokens = parser.parse("option (a) (allthethings)");
+        assert_eq!(tokens, vec![
+            Token::Text("option (a) "),
+            Token::Emoticon { shortcut: "allthethings", emoticon: Some(&emoticons[0]) }
+        ]);
+
+        let tokens = MessageParser::new().parse("(shrug)!");
+        assert_eq!(tokens, vec![Token::Emoticon { shortcut: "shrug", emoticon: None }, Token::Text("!")]);
+    }
+
+    #[test]
+    fn unit_parse_commands_and_code() {
+        let parser = MessageParser::new();
+        assert_eq!(parser.parse("/deploy api prod"), vec![Token::Command { name: "deploy", args: "api prod" }]);
+        assert_eq!(parser.parse("/code let x = 1;"), vec![Token::Code("let x = 1;")]);
+        assert_eq!(parser.parse("run ```\ncargo test\n``` now"), vec![
+            Token::Text("run "),
+            Token::Code("cargo test"),
+            Token::Text(" now")
+        ]);
+    }
+}
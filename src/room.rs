@@ -1,11 +1,22 @@
-use util::{Privacy, AppendToQueryParams};
+use error::Error;
+use util::{Privacy, AppendToQueryParams, validate_length, validate_max_results};
 use message::{Color, MessageFormat};
+use user::User;
 
 use url::UrlQuery;
 use url::form_urlencoded::Serializer;
 
 
-#[derive(Debug, Hash, Eq, PartialEq)]
+/// Maximum length of a room name
+pub const ROOM_NAME_MAX_LENGTH: usize = 50;
+/// Maximum length of a room topic
+pub const ROOM_TOPIC_MAX_LENGTH: usize = 250;
+/// Maximum length of a message sent with `send_message`
+pub const MESSAGE_MAX_LENGTH: usize = 1000;
+/// Maximum length of a room notification message
+pub const NOTIFICATION_MAX_LENGTH: usize = 10000;
+
+#[derive(Debug, Default, Hash, Eq, PartialEq)]
 pub struct RoomsRequest {
     pub start_index: Option<u64>,
     pub max_results: Option<u64>,
@@ -13,6 +24,43 @@ pub struct RoomsRequest {
     pub include_archived: Option<bool>
 }
 
+impl RoomsRequest {
+    pub fn builder() -> RoomsRequestBuilder {
+        RoomsRequestBuilder::default()
+    }
+    pub fn validate(&self) -> Result<(), Error> {
+        validate_max_results(self.max_results)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RoomsRequestBuilder {
+    request: RoomsRequest
+}
+
+impl RoomsRequestBuilder {
+    pub fn start_index(mut self, start_index: u64) -> Self {
+        self.request.start_index = Some(start_index);
+        self
+    }
+    pub fn max_results(mut self, max_results: u64) -> Self {
+        self.request.max_results = Some(max_results);
+        self
+    }
+    pub fn include_private(mut self, include_private: bool) -> Self {
+        self.request.include_private = Some(include_private);
+        self
+    }
+    pub fn include_archived(mut self, include_archived: bool) -> Self {
+        self.request.include_archived = Some(include_archived);
+        self
+    }
+    pub fn build(self) -> Result<RoomsRequest, Error> {
+        try!(self.request.validate());
+        Ok(self.request)
+    }
+}
+
 impl AppendToQueryParams for RoomsRequest {
     fn append_to(&self, query: &mut Serializer<UrlQuery>){
         self.start_index.map(|start_index| query.append_pair("start-index", &start_index.to_string()));
@@ -22,7 +70,7 @@ impl AppendToQueryParams for RoomsRequest {
     }
 }
 
-#[derive(Debug, Hash, Eq, PartialEq, Deserialize)]
+#[derive(Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Rooms {
     #[serde(rename = "startIndex")]
     pub start_index: u64,
@@ -32,7 +80,7 @@ pub struct Rooms {
     pub links: RoomsLinks
 }
 
-#[derive(Debug, Hash, Eq, PartialEq, Deserialize)]
+#[derive(Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct RoomsLinks {
     #[serde(rename = "self")]
     pub self_: String,
@@ -40,15 +88,16 @@ pub struct RoomsLinks {
     pub next: Option<String>
 }
 
-#[derive(Debug, Hash, Eq, PartialEq, Deserialize)]
+#[derive(Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Room {
     pub name: String,
     pub id: u64,
     pub links: RoomDetailLinks
 }
 
-#[derive(Debug, Hash, Eq, PartialEq, Deserialize)]
+#[derive(Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct RoomDetail {
+    #[serde(default)]
     pub xmpp_jid: String,
     pub statistics: RoomDetailStatistics,
     pub name: String,
@@ -60,30 +109,50 @@ pub struct RoomDetail {
     pub topic: String,
     pub avatar_url: Option<String>,
     pub id: u64,
-    pub guest_access_url: Option<String>
+    pub guest_access_url: Option<String>,
+    /// Optional only so that rooms sent without an owner can still be read;
+    /// `RoomUpdate::from_room` refuses to write them back
+    pub owner: Option<RoomDetailOwner>,
+    /// Users currently in the room
+    #[serde(default)]
+    pub participants: Vec<User>,
+    /// The room's delegate admin visibility setting, absent on older servers
+    pub delegate_admin_visibility: Option<bool>,
+    /// Changes whenever the room is updated
+    pub version: Option<String>
 }
 
-#[derive(Debug, Hash, Eq, PartialEq, Deserialize)]
+#[derive(Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct RoomDetailStatistics {
     pub links: RoomDetailStatisticsLinks
 }
 
-#[derive(Debug, Hash, Eq, PartialEq, Deserialize)]
+#[derive(Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct RoomDetailStatisticsLinks {
     #[serde(rename = "self")]
     pub self_: String
 }
 
-#[derive(Debug, Hash, Eq, PartialEq, Deserialize)]
+/// [Get room statistics](https://www.hipchat.com/docs/apiv2/method/get_room_statistics)
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RoomStatistics {
+    pub messages_sent: Option<u64>,
+    /// Time of the last message, or `None` if the room was never used
+    pub last_active: Option<String>
+}
+
+#[derive(Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct RoomDetailLinks {
     #[serde(rename = "self")]
     pub self_: String,
+    #[serde(default)]
     pub webhooks: String,
     pub members: Option<String>,
+    #[serde(default)]
     pub participants: String
 }
 
-#[derive(Debug, Hash, Eq, PartialEq, Deserialize)]
+#[derive(Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct RoomDetailOwner {
     pub mention_name: String,
     pub id: u64,
@@ -91,13 +160,13 @@ pub struct RoomDetailOwner {
     pub name: String
 }
 
-#[derive(Debug, Hash, Eq, PartialEq, Deserialize)]
+#[derive(Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct RoomDetailOwnerLinks {
     #[serde(rename = "self")]
     pub self_: String
 }
 
-#[derive(Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct RoomUpdate {
     pub name: Option<String>,
     pub privacy: Option<Privacy>,
@@ -107,12 +176,109 @@ pub struct RoomUpdate {
     pub owner: Option<RoomUpdateOwner>
 }
 
-#[derive(Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
+impl RoomUpdate {
+    pub fn builder() -> RoomUpdateBuilder {
+        RoomUpdateBuilder::default()
+    }
+    /// Overwrites the fields that are set in `patch`
+    pub fn merge(&mut self, patch: &RoomUpdate) {
+        if patch.name.is_some() {
+            self.name = patch.name.clone();
+        }
+        if patch.privacy.is_some() {
+            self.privacy = patch.privacy.clone();
+        }
+        if patch.is_archived.is_some() {
+            self.is_archived = patch.is_archived;
+        }
+        if patch.is_guest_accessible.is_some() {
+            self.is_guest_accessible = patch.is_guest_accessible;
+        }
+        if patch.topic.is_some() {
+            self.topic = patch.topic.clone();
+        }
+        if patch.owner.is_some() {
+            self.owner = patch.owner.clone();
+        }
+    }
+    pub fn validate(&self) -> Result<(), Error> {
+        if let Some(ref name) = self.name {
+            try!(validate_length("name", name, 1, ROOM_NAME_MAX_LENGTH));
+        }
+        if let Some(ref topic) = self.topic {
+            try!(validate_length("topic", topic, 0, ROOM_TOPIC_MAX_LENGTH));
+        }
+        Ok(())
+    }
+}
+
+impl RoomUpdate {
+    /// A full update that writes the room back as it is, for HipChat's PUT which requires every field
+    ///
+    /// Fails with `Error::Validation` if the room has no owner, rather than sending the update without one.
+    pub fn from_room(room: &RoomDetail) -> Result<RoomUpdate, Error> {
+        RoomUpdate::from_patched_room(room, &RoomUpdate::default())
+    }
+    /// Like `from_room`, with the fields set in `patch` overwriting the room's
+    pub fn from_patched_room(room: &RoomDetail, patch: &RoomUpdate) -> Result<RoomUpdate, Error> {
+        let mut update = RoomUpdate {
+            name: Some(room.name.clone()),
+            privacy: Some(room.privacy.clone()),
+            is_archived: Some(room.is_archived),
+            is_guest_accessible: Some(room.is_guest_accessible),
+            topic: Some(room.topic.clone()),
+            owner: room.owner.as_ref().map(|owner| RoomUpdateOwner { id: Some(owner.id.to_string()) })
+        };
+        update.merge(patch);
+        if update.owner.is_none() {
+            return Err(Error::Validation(format!("room {} has no owner, which an update requires", room.id)));
+        }
+        Ok(update)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RoomUpdateBuilder {
+    update: RoomUpdate
+}
+
+impl RoomUpdateBuilder {
+    pub fn name<T: Into<String>>(mut self, name: T) -> Self {
+        self.update.name = Some(name.into());
+        self
+    }
+    pub fn privacy(mut self, privacy: Privacy) -> Self {
+        self.update.privacy = Some(privacy);
+        self
+    }
+    pub fn is_archived(mut self, is_archived: bool) -> Self {
+        self.update.is_archived = Some(is_archived);
+        self
+    }
+    pub fn is_guest_accessible(mut self, is_guest_accessible: bool) -> Self {
+        self.update.is_guest_accessible = Some(is_guest_accessible);
+        self
+    }
+    pub fn topic<T: Into<String>>(mut self, topic: T) -> Self {
+        self.update.topic = Some(topic.into());
+        self
+    }
+    pub fn owner_id<T: Into<String>>(mut self, owner_id: T) -> Self {
+        self.update.owner = Some(RoomUpdateOwner { id: Some(owner_id.into()) });
+        self
+    }
+    pub fn build(self) -> Result<RoomUpdate, Error> {
+        try!(self.update.validate());
+        Ok(self.update)
+    }
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct RoomUpdateOwner {
     pub id: Option<String>
 }
 
-#[derive(Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Notification {
     pub color: Color,
     pub message: String,
@@ -131,6 +297,43 @@ impl Default for Notification {
     }
 }
 
+impl Notification {
+    pub fn builder() -> NotificationBuilder {
+        NotificationBuilder::default()
+    }
+    pub fn validate(&self) -> Result<(), Error> {
+        validate_length("message", &self.message, 1, NOTIFICATION_MAX_LENGTH)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct NotificationBuilder {
+    notification: Notification
+}
+
+impl NotificationBuilder {
+    pub fn color(mut self, color: Color) -> Self {
+        self.notification.color = color;
+        self
+    }
+    pub fn message<T: Into<String>>(mut self, message: T) -> Self {
+        self.notification.message = message.into();
+        self
+    }
+    pub fn notify(mut self, notify: bool) -> Self {
+        self.notification.notify = notify;
+        self
+    }
+    pub fn message_format(mut self, message_format: MessageFormat) -> Self {
+        self.notification.message_format = message_format;
+        self
+    }
+    pub fn build(self) -> Result<Notification, Error> {
+        try!(self.notification.validate());
+        Ok(self.notification)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -179,4 +382,194 @@ mod test {
 
         assert_eq!(Some("start-index=1&max-results=10&include-private=true&include-archived=true"), url.query());
     }
+
+    #[test]
+    fn unit_rooms_request_builder() {
+        let expected = RoomsRequest{ start_index: Some(5),
+                                     max_results: Some(50),
+                                     include_private: None,
+                                     include_archived: Some(true) };
+        let actual = RoomsRequest::builder()
+            .start_index(5)
+            .max_results(50)
+            .include_archived(true)
+            .build()
+            .unwrap();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn unit_rooms_request_builder_rejects_max_results() {
+        match RoomsRequest::builder().max_results(1001).build() {
+            Err(Error::Validation(_)) => (),
+            other => panic!("expected validation error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn unit_room_update_builder() {
+        let actual = RoomUpdate::builder()
+            .name("ops")
+            .privacy(Privacy::Private)
+            .topic("Deploys")
+            .owner_id("42")
+            .build()
+            .unwrap();
+        assert_eq!(actual.name, Some("ops".to_owned()));
+        assert_eq!(actual.privacy, Some(Privacy::Private));
+        assert_eq!(actual.is_archived, None);
+        assert_eq!(actual.topic, Some("Deploys".to_owned()));
+        assert_eq!(actual.owner, Some(RoomUpdateOwner { id: Some("42".to_owned()) }));
+    }
+
+    #[test]
+    fn unit_room_update_builder_rejects_long_topic() {
+        let topic = "a".repeat(ROOM_TOPIC_MAX_LENGTH + 1);
+        match RoomUpdate::builder().topic(topic).build() {
+            Err(Error::Validation(_)) => (),
+            other => panic!("expected validation error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn unit_notification_builder() {
+        let actual = Notification::builder()
+            .color(Color::Red)
+            .message("build failed")
+            .notify(true)
+            .message_format(MessageFormat::Text)
+            .build()
+            .unwrap();
+        let expected = Notification {
+            color: Color::Red,
+            message: "build failed".to_owned(),
+            notify: true,
+            message_format: MessageFormat::Text
+        };
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn unit_notification_builder_rejects_empty_and_long_messages() {
+        assert!(Notification::builder().build().is_err());
+        let message = "a".repeat(NOTIFICATION_MAX_LENGTH + 1);
+        assert!(Notification::builder().message(message).build().is_err());
+    }
+
+    #[test]
+    fn unit_room_update_from_room_detail_and_merge() {
+        let room: RoomDetail = serde_json::from_str(r#"{
+            "xmpp_jid": "1_ops@conf.hipchat.com", "name": "Ops", "id": 1, "created": "2016-01-01T00:00:00+00:00",
+            "is_archived": false, "privacy": "private", "is_guest_accessible": true, "topic": "Incidents",
+            "avatar_url": null, "guest_access_url": null,
+            "owner": {"id": 5, "name": "Ada", "mention_name": "ada", "links": {"self": "https://api.hipchat.com/v2/user/5"}},
+            "statistics": {"links": {"self": "https://api.hipchat.com/v2/room/1/statistics"}},
+            "links": {"self": "https://api.hipchat.com/v2/room/1", "webhooks": "w", "participants": "p"}
+        }"#).unwrap();
+        let update = RoomUpdate::from_patched_room(&room, &RoomUpdate::builder().topic("Outage").build().unwrap()).unwrap();
+        assert_eq!(serde_json::to_string(&update).unwrap(),
+                   r#"{"name":"Ops","privacy":"private","is_archived":false,"is_guest_accessible":true,"topic":"Outage","owner":{"id":"5"}}"#);
+    }
+
+    #[test]
+    fn unit_room_update_from_room_without_owner() {
+        let room: RoomDetail = serde_json::from_str(r#"{
+            "xmpp_jid": "1_ops@conf.hipchat.com", "name": "Ops", "id": 1, "created": "2016-01-01T00:00:00+00:00",
+            "is_archived": false, "privacy": "private", "is_guest_accessible": true, "topic": "Incidents",
+            "avatar_url": null, "guest_access_url": null, "owner": null,
+            "statistics": {"links": {"self": "https://api.hipchat.com/v2/room/1/statistics"}},
+            "links": {"self": "https://api.hipchat.com/v2/room/1", "webhooks": "w", "participants": "p"}
+        }"#).unwrap();
+        match RoomUpdate::from_room(&room) {
+            Err(Error::Validation(_)) => (),
+            other => panic!("expected validation error, got {:?}", other)
+        }
+        let update = RoomUpdate::from_patched_room(&room, &RoomUpdate::builder().owner_id("5").build().unwrap()).unwrap();
+        assert_eq!(update.owner, Some(RoomUpdateOwner { id: Some("5".to_owned()) }));
+    }
+
+    #[test]
+    fn unit_deserialize_room_detail_fixture() {
+        let room: RoomDetail = serde_json::from_str(r#"{
+            "avatar_url": null,
+            "created": "2016-06-24T13:37:22+00:00",
+            "delegate_admin_visibility": null,
+            "guest_access_url": null,
+            "id": 2883426,
+            "is_archived": false,
+            "is_guest_accessible": false,
+            "last_active": "2017-03-01T16:35:31+00:00",
+            "links": {
+                "participants": "https://api.hipchat.com/v2/room/2883426/participant",
+                "self": "https://api.hipchat.com/v2/room/2883426",
+                "webhooks": "https://api.hipchat.com/v2/room/2883426/webhook"
+            },
+            "name": "Ops",
+            "owner": {
+                "id": 3744112,
+                "links": {"self": "https://api.hipchat.com/v2/user/3744112"},
+                "mention_name": "ada",
+                "name": "Ada Lovelace",
+                "version": "6A6C2B1D"
+            },
+            "participants": [
+                {
+                    "id": 3744112,
+                    "links": {"self": "https://api.hipchat.com/v2/user/3744112"},
+                    "mention_name": "ada",
+                    "name": "Ada Lovelace",
+                    "version": "6A6C2B1D"
+                }
+            ],
+            "privacy": "public",
+            "statistics": {"links": {"self": "https://api.hipchat.com/v2/room/2883426/statistics"}},
+            "topic": "Incidents and deploys",
+            "version": "K3T0HJNW",
+            "xmpp_jid": "551204_ops@conf.hipchat.com"
+        }"#).unwrap();
+        assert_eq!(room.owner.unwrap().mention_name, "ada");
+        assert_eq!(room.participants.len(), 1);
+        assert_eq!(room.participants[0].id, 3744112);
+        assert_eq!(room.delegate_admin_visibility, None);
+        assert_eq!(room.version, Some("K3T0HJNW".to_owned()));
+    }
+
+    #[test]
+    fn unit_deserialize_room_detail_with_missing_fields() {
+        let room: RoomDetail = serde_json::from_str(r#"{
+            "id": 1,
+            "name": "Ops",
+            "privacy": "private",
+            "created": "2016-06-24T13:37:22+00:00",
+            "is_archived": false,
+            "is_guest_accessible": true,
+            "topic": "Incidents",
+            "avatar_url": null,
+            "guest_access_url": null,
+            "links": {"self": "https://api.hipchat.com/v2/room/1"},
+            "statistics": {"links": {"self": "https://api.hipchat.com/v2/room/1/statistics"}}
+        }"#).unwrap();
+        assert_eq!(room.xmpp_jid, "");
+        assert!(room.owner.is_none());
+        assert!(room.participants.is_empty());
+        assert_eq!(room.links.webhooks, "");
+    }
+
+    #[test]
+    fn unit_deserialize_room_detail_requires_writable_fields() {
+        // A room read without its topic would have it cleared by `patch_room`
+        let room: Result<RoomDetail, _> = serde_json::from_str(r#"{
+            "id": 1,
+            "name": "Ops",
+            "privacy": "private",
+            "created": "2016-06-24T13:37:22+00:00",
+            "is_archived": false,
+            "is_guest_accessible": true,
+            "avatar_url": null,
+            "guest_access_url": null,
+            "links": {"self": "https://api.hipchat.com/v2/room/1"},
+            "statistics": {"links": {"self": "https://api.hipchat.com/v2/room/1/statistics"}}
+        }"#);
+        assert!(room.is_err());
+    }
 }
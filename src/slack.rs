@@ -0,0 +1,456 @@
+//! Conversion of room history to the Slack export layout
+//!
+//! The output directory holds `channels.json`, `users.json` and one
+//! `{channel}/{YYYY-MM-DD}.json` file per channel and day (UTC), which Slack
+//! and Mattermost's Slack importer both accept. Nothing here talks to
+//! HipChat; fetch rooms, users and history with `Client` first.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::Path;
+
+use serde_json::{self};
+
+use error::Error;
+use message::{Message, MessageType};
+use parse::{MessageParser, Token};
+use room::RoomDetail;
+use user::UserDetail;
+
+/// Longest channel name Slack accepts
+const CHANNEL_NAME_MAX_LENGTH: usize = 80;
+
+#[derive(Debug, Clone, Default, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SlackTopic {
+    pub value: String,
+    pub creator: String,
+    pub last_set: i64
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SlackChannel {
+    pub id: String,
+    pub name: String,
+    pub created: i64,
+    pub creator: String,
+    pub is_archived: bool,
+    pub is_general: bool,
+    pub members: Vec<String>,
+    pub topic: SlackTopic,
+    pub purpose: SlackTopic
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SlackProfile {
+    pub real_name: String,
+    pub display_name: String,
+    pub email: Option<String>,
+    pub title: String
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SlackUser {
+    pub id: String,
+    pub name: String,
+    pub real_name: String,
+    pub deleted: bool,
+    pub is_bot: bool,
+    pub tz: Option<String>,
+    pub profile: SlackProfile
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SlackMessage {
+    #[serde(rename = "type")]
+    pub message_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subtype: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    pub text: String,
+    pub ts: String
+}
+
+/// A Slack export being assembled from HipChat rooms, users and messages
+#[derive(Debug, Default)]
+pub struct SlackExport {
+    users: BTreeMap<u64, SlackUser>,
+    mention_ids: BTreeMap<String, String>,
+    channels: Vec<SlackChannel>,
+    /// Messages by channel name, then by day
+    days: BTreeMap<String, BTreeMap<String, Vec<SlackMessage>>>
+}
+
+impl SlackExport {
+    pub fn new() -> Self {
+        SlackExport::default()
+    }
+    /// Adds users, so that they appear in `users.json` and their mentions are converted
+    pub fn add_users(&mut self, users: &[UserDetail]) {
+        for user in users {
+            self.add_user(user);
+        }
+    }
+    /// Adds a room as a channel with its messages. Message senders are added as users.
+    ///
+    /// Add users before rooms so that mentions of people who never posted are converted too.
+    pub fn add_room(&mut self, room: &RoomDetail, messages: &[Message]) -> Result<(), Error> {
+        for message in messages {
+            if let Some(ref from) = message.from {
+                self.add_user(from);
+            }
+        }
+
+        let name = self.channel_name(room);
+        let mut members = Vec::new();
+        let mut created = None;
+        let mut topic = SlackTopic::default();
+        for message in messages {
+            let (secs, micros) = match parse_timestamp(&message.date) {
+                Some(timestamp) => timestamp,
+                None => return Err(Error::Validation(format!("invalid message date {:?}", message.date)))
+            };
+            let slack_message = self.convert(message, secs, micros);
+            if let Some(ref user) = slack_message.user {
+                if !members.contains(user) {
+                    members.push(user.clone());
+                }
+            }
+            if message.message_type == MessageType::Topic && secs >= topic.last_set {
+                topic = SlackTopic {
+                    value: message.message.clone(),
+                    creator: slack_message.user.clone().unwrap_or_default(),
+                    last_set: secs
+                };
+            }
+            created = Some(created.map_or(secs, |created: i64| created.min(secs)));
+            self.days.entry(name.clone()).or_default()
+                .entry(day(secs)).or_default()
+                .push(slack_message);
+        }
+        if let Some(days) = self.days.get_mut(&name) {
+            for messages in days.values_mut() {
+                messages.sort_by(|a, b| a.ts.cmp(&b.ts));
+            }
+        }
+
+        // The earliest message stands in for a creation date that cannot be parsed
+        let created = parse_timestamp(&room.created).map(|(secs, _)| secs).or(created);
+        self.channels.push(SlackChannel {
+            id: format!("C{}", room.id),
+            name: name,
+            created: created.unwrap_or(0),
+            creator: members.first().cloned().unwrap_or_default(),
+            is_archived: room.is_archived,
+            is_general: false,
+            members: members,
+            topic: topic,
+            purpose: SlackTopic::default()
+        });
+        Ok(())
+    }
+    pub fn channels(&self) -> &[SlackChannel] {
+        &self.channels
+    }
+    pub fn users(&self) -> Vec<&SlackUser> {
+        self.users.values().collect()
+    }
+    /// Messages posted to `channel` on `day`, e.g. `2017-03-20`
+    pub fn messages(&self, channel: &str, day: &str) -> Option<&[SlackMessage]> {
+        self.days.get(channel).and_then(|days| days.get(day)).map(|messages| messages.as_slice())
+    }
+    /// Writes the export into `dir`, creating it if needed
+    pub fn write_to<P: AsRef<Path>>(&self, dir: P) -> Result<(), Error> {
+        let dir = dir.as_ref();
+        try!(fs::create_dir_all(dir));
+        try!(write_json(&dir.join("channels.json"), &self.channels));
+        try!(write_json(&dir.join("users.json"), &self.users()));
+        for (channel, days) in &self.days {
+            let channel_dir = dir.join(channel);
+            try!(fs::create_dir_all(&channel_dir));
+            for (day, messages) in days {
+                try!(write_json(&channel_dir.join(format!("{}.json", day)), messages));
+            }
+        }
+        Ok(())
+    }
+    fn add_user(&mut self, user: &UserDetail) {
+        let id = format!("U{}", user.id);
+        self.mention_ids.insert(user.mention_name.to_lowercase(), id.clone());
+        self.users.entry(user.id).or_insert_with(|| SlackUser {
+            id: id,
+            name: user.mention_name.to_lowercase(),
+            real_name: user.name.clone(),
+            deleted: user.is_deleted.unwrap_or(false),
+            is_bot: false,
+            tz: user.timezone.clone(),
+            profile: SlackProfile {
+                real_name: user.name.clone(),
+                display_name: user.mention_name.clone(),
+                email: user.email.clone(),
+                title: user.title.clone().unwrap_or_default()
+            }
+        });
+    }
+    /// A unique, Slack-safe channel name for the room, at most `CHANNEL_NAME_MAX_LENGTH` ASCII characters
+    fn channel_name(&self, room: &RoomDetail) -> String {
+        let name: String = room.name.to_lowercase().chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '-' })
+            .take(CHANNEL_NAME_MAX_LENGTH)
+            .collect();
+        if !name.is_empty() && !self.channels.iter().any(|channel| channel.name == name) {
+            return name;
+        }
+        let suffix = format!("-{}", room.id);
+        let name = &name[..name.len().min(CHANNEL_NAME_MAX_LENGTH.saturating_sub(suffix.len()))];
+        format!("{}{}", name, suffix).trim_start_matches('-').to_owned()
+    }
+    fn convert(&self, message: &Message, secs: i64, micros: u32) -> SlackMessage {
+        let user = message.from.as_ref().map(|from| format!("U{}", from.id));
+        let mut text = self.convert_text(&message.message);
+        if let Some(ref file) = message.file {
+            if !text.is_empty() {
+                text.push('\n');
+            }
+            text.push_str(&format!("<{}|{}>", file.url, escape(&file.name)));
+        }
+        let subtype = match (message.message_type.clone(), user.is_some()) {
+            (MessageType::Topic, _) => Some("channel_topic"),
+            (_, false) => Some("bot_message"),
+            _ => None
+        };
+        SlackMessage {
+            message_type: "message".to_owned(),
+            subtype: subtype.map(|subtype| subtype.to_owned()),
+            username: if user.is_none() { Some(message.from_name.clone().unwrap_or_else(|| "HipChat".to_owned())) } else { None },
+            user: user,
+            text: text,
+            ts: format!("{}.{:06}", secs, micros)
+        }
+    }
+    /// Rewrites known `@mentions` as `<@U123>` and URLs and code in Slack markup,
+    /// escaping `&`, `<` and `>` everywhere else
+    fn convert_text(&self, text: &str) -> String {
+        let parser = MessageParser::new().emoticons(&[]);
+        parser.parse(text).into_iter().map(|token| match token {
+            Token::Mention { name, .. } => match self.mention_ids.get(&name.to_lowercase()) {
+                Some(id) => format!("<@{}>", id),
+                None if name == "all" || name == "here" => format!("<!{}>", if name == "all" { "channel" } else { "here" }),
+                None => format!("@{}", name)
+            },
+            Token::Url(url) => format!("<{}>", url),
+            Token::Code(code) => format!("```{}```", escape(code)),
+            Token::Command { name, args: "" } => format!("/{}", escape(name)),
+            Token::Command { name, args } => format!("/{} {}", escape(name), escape(args)),
+            Token::Emoticon { shortcut, .. } => format!("({})", escape(shortcut)),
+            Token::Text(text) => escape(text)
+        }).collect()
+    }
+}
+
+/// Escapes the characters Slack treats as markup
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn write_json<T: ::serde::Serialize>(path: &Path, value: &T) -> Result<(), Error> {
+    let mut file = try!(File::create(path));
+    try!(file.write_all(serde_json::to_string_pretty(value).unwrap().as_bytes()));
+    Ok(())
+}
+
+/// Seconds since the epoch and microseconds for an ISO 8601 date such as `2017-03-20T12:00:00.123456+04:00`
+fn parse_timestamp(date: &str) -> Option<(i64, u32)> {
+    fn number(s: &str, range: ::std::ops::Range<usize>) -> Option<i64> {
+        s.get(range).and_then(|part| part.parse().ok())
+    }
+    let parts = (number(date, 0..4), number(date, 5..7), number(date, 8..10), number(date, 11..13), number(date, 14..16), number(date, 17..19));
+    let (year, month, day, hour, minute, second) = match parts {
+        (Some(year), Some(month), Some(day), Some(hour), Some(minute), Some(second)) => (year, month, day, hour, minute, second),
+        _ => return None
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let mut rest = &date[19..];
+    let mut micros = 0;
+    if rest.starts_with('.') {
+        let digits: String = rest[1..].chars().take_while(|c| c.is_ascii_digit()).collect();
+        rest = &rest[1 + digits.len()..];
+        let padded: String = digits.chars().chain(::std::iter::repeat('0')).take(6).collect();
+        micros = match padded.parse() {
+            Ok(micros) => micros,
+            Err(_) => return None
+        };
+    }
+    let offset = match rest {
+        "" | "Z" => 0,
+        _ => {
+            let sign = match rest.as_bytes()[0] {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None
+            };
+            let digits = rest[1..].replace(':', "");
+            match (number(&digits, 0..2), number(&digits, 2..4)) {
+                (Some(hours), Some(minutes)) => sign * (hours * 3600 + minutes * 60),
+                _ => return None
+            }
+        }
+    };
+    let secs = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset;
+    Some((secs, micros))
+}
+
+/// The UTC day of a timestamp, as `YYYY-MM-DD`
+fn day(secs: i64) -> String {
+    let days = if secs >= 0 { secs / 86400 } else { (secs - 86399) / 86400 };
+    let (year, month, day) = civil_from_days(days);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = if days >= 0 { days } else { days - 146096 } / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (if month <= 2 { year_of_era + era * 400 + 1 } else { year_of_era + era * 400 }, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+
+    const USERS: &str = r#"[
+        {"id": 1, "name": "Ada Lovelace", "mention_name": "ada", "email": "ada@example.com", "title": "Engineer",
+         "timezone": "Europe/London", "xmpp_jid": null, "photo_url": null, "presence": null, "is_deleted": false,
+         "is_guest": false, "is_group_admin": false, "created": null, "last_active": null,
+         "links": {"self": "https://api.hipchat.com/v2/user/1"}},
+        {"id": 2, "name": "Grace Hopper", "mention_name": "grace", "email": null, "title": null,
+         "timezone": null, "xmpp_jid": null, "photo_url": null, "presence": null, "is_deleted": true,
+         "is_guest": false, "is_group_admin": false, "created": null, "last_active": null,
+         "links": {"self": "https://api.hipchat.com/v2/user/2"}}
+    ]"#;
+
+    const ROOM: &str = r#"{"id": 7, "name": "Ops Team", "xmpp_jid": "7_ops@conf.hipchat.com",
+        "created": "2017-01-01T00:00:00+00:00", "is_archived": true, "privacy": "public",
+        "is_guest_accessible": false, "topic": "", "avatar_url": null, "guest_access_url": null, "owner": null,
+        "statistics": {"links": {"self": "https://api.hipchat.com/v2/room/7/statistics"}},
+        "links": {"self": "https://api.hipchat.com/v2/room/7", "webhooks": "w", "participants": "p"}}"#;
+
+    const MESSAGES: &str = r#"[
+        {"id": "m1", "date": "2017-03-20T23:30:00.250000+00:00", "message": "hey @Grace, see https://ci.example.com/1. <b>&</b>",
+         "type": "message", "mentions": [], "file": null,
+         "from": {"id": 1, "name": "Ada Lovelace", "mention_name": "ada", "links": {"self": "https://api.hipchat.com/v2/user/1"}}},
+        {"id": "m2", "date": "2017-03-21T03:00:00+02:00", "message": "Deploys", "type": "topic", "mentions": [],
+         "file": null, "from": {"id": 2, "name": "Grace Hopper", "mention_name": "grace", "links": {"self": "https://api.hipchat.com/v2/user/2"}}},
+        {"id": "m3", "date": "2017-03-21T08:00:00Z", "message": "build passed", "type": "notification", "mentions": [],
+         "from": "CI", "file": {"url": "https://files.example.com/log.txt", "thumb_url": null, "name": "log<1>.txt", "size": 3}}
+    ]"#;
+
+    fn export() -> SlackExport {
+        let users: Vec<UserDetail> = serde_json::from_str(USERS).unwrap();
+        let room: RoomDetail = serde_json::from_str(ROOM).unwrap();
+        let messages: Vec<Message> = serde_json::from_str(MESSAGES).unwrap();
+        let mut export = SlackExport::new();
+        export.add_users(&users);
+        export.add_room(&room, &messages).unwrap();
+        export
+    }
+
+    #[test]
+    fn unit_parse_timestamp() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Some((0, 0)));
+        assert_eq!(parse_timestamp("2017-03-20T12:00:00.5+04:00"), Some((1489996800, 500000)));
+        assert_eq!(parse_timestamp("2017-03-20"), None);
+        assert_eq!(day(1489996800), "2017-03-20");
+        assert_eq!(day(951782400), "2000-02-29");
+    }
+
+    #[test]
+    fn unit_slack_export_converts_rooms_users_and_messages() {
+        let export = export();
+        let channel = &export.channels()[0];
+        assert_eq!(channel.id, "C7");
+        assert_eq!(channel.name, "ops-team");
+        assert!(channel.is_archived);
+        assert_eq!(channel.members, vec!["U1", "U2"]);
+        assert_eq!(channel.topic.value, "Deploys");
+        assert_eq!(channel.created, 1483228800);
+
+        let users = export.users();
+        assert_eq!(users[1].name, "grace");
+        assert!(users[1].deleted);
+
+        let first_day = export.messages("ops-team", "2017-03-20").unwrap();
+        assert_eq!(first_day.len(), 1);
+        assert_eq!(first_day[0].text, "hey <@U2>, see <https://ci.example.com/1>. &lt;b&gt;&amp;&lt;/b&gt;");
+        assert_eq!(first_day[0].ts, "1490052600.250000");
+
+        let second_day = export.messages("ops-team", "2017-03-21").unwrap();
+        assert_eq!(second_day[0].subtype, Some("channel_topic".to_owned()));
+        assert_eq!(second_day[1].subtype, Some("bot_message".to_owned()));
+        assert_eq!(second_day[1].username, Some("CI".to_owned()));
+        assert_eq!(second_day[1].text, "build passed\n<https://files.example.com/log.txt|log&lt;1&gt;.txt>");
+    }
+
+    #[test]
+    fn unit_slack_export_dates_channel_by_earliest_message_without_room_created() {
+        let mut room: RoomDetail = serde_json::from_str(ROOM).unwrap();
+        room.created = "unknown".to_owned();
+        let messages: Vec<Message> = serde_json::from_str(MESSAGES).unwrap();
+        let mut export = SlackExport::new();
+        export.add_room(&room, &messages).unwrap();
+        assert_eq!(export.channels()[0].created, 1490052600);
+    }
+
+    #[test]
+    fn unit_slack_export_writes_directory_layout() {
+        let dir = env::temp_dir().join(format!("hipchat-slack-export-{}", ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        export().write_to(&dir).unwrap();
+
+        let mut body = String::new();
+        File::open(dir.join("ops-team").join("2017-03-21.json")).unwrap().read_to_string(&mut body).unwrap();
+        let messages: Vec<SlackMessage> = serde_json::from_str(&body).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert!(dir.join("channels.json").exists());
+        assert!(dir.join("users.json").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unit_slack_channel_names_are_ascii_and_capped() {
+        let mut room: RoomDetail = serde_json::from_str(ROOM).unwrap();
+        room.name = format!("Café {}", "x".repeat(90));
+        let mut export = SlackExport::new();
+        export.add_room(&room, &[]).unwrap();
+        room.id = 123456;
+        export.add_room(&room, &[]).unwrap();
+
+        let first = &export.channels()[0].name;
+        let second = &export.channels()[1].name;
+        assert!(first.starts_with("caf--xxx"));
+        assert_eq!(first.len(), 80);
+        assert!(second.ends_with("x-123456"));
+        assert_eq!(second.len(), 80);
+    }
+}
@@ -0,0 +1,134 @@
+//! A minimal HTTP server that replays canned responses, for unit tests
+
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::TcpListener;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+
+use serde_json;
+
+use client::Client;
+
+/// A request received by the `TestServer`
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String
+}
+
+impl RecordedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub struct TestServer {
+    pub origin: String,
+    pub requests: Receiver<RecordedRequest>
+}
+
+impl TestServer {
+    /// Serves each response, in order, to one connection apiece
+    pub fn new(responses: Vec<String>) -> TestServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let origin = format!("http://{}", listener.local_addr().unwrap());
+        let (tx, rx) = channel();
+
+        thread::spawn(move || {
+            for response in responses {
+                let (stream, _) = match listener.accept() {
+                    Ok(conn) => conn,
+                    Err(_) => return
+                };
+                let mut reader = BufReader::new(stream);
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let mut parts = line.split_whitespace();
+                let method = parts.next().unwrap_or("").to_owned();
+                let path = parts.next().unwrap_or("").to_owned();
+
+                let mut headers = Vec::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(i) = line.find(':') {
+                        headers.push((line[..i].trim().to_owned(), line[i + 1..].trim().to_owned()));
+                    }
+                }
+                let length = headers.iter()
+                    .find(|(key, _)| key.eq_ignore_ascii_case("content-length"))
+                    .and_then(|(_, value)| value.parse::<usize>().ok())
+                    .unwrap_or(0);
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+
+                let _ = tx.send(RecordedRequest {
+                    method: method,
+                    path: path,
+                    headers: headers,
+                    body: String::from_utf8(body).unwrap()
+                });
+                let mut stream = reader.into_inner();
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+
+        TestServer {
+            origin: origin,
+            requests: rx
+        }
+    }
+}
+
+/// Formats a complete HTTP response with a body
+pub fn response(status: &str, headers: &[(&str, &str)], body: &str) -> String {
+    let mut response = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n", status, body.len());
+    for &(name, value) in headers {
+        response.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    response.push_str("\r\n");
+    response.push_str(body);
+    response
+}
+
+/// A `200 OK` response with a JSON body
+pub fn json(body: &str) -> String {
+    response("200 OK", &[("Content-Type", "application/json")], body)
+}
+
+/// A `Client` for `server`, with a fixed token
+pub fn client(server: &TestServer) -> Client {
+    Client::with_hyper_client(server.origin.clone(), "token", ::hyper::Client::new())
+}
+
+/// A room as returned by get room, owned by user 5
+pub fn room(id: u64, name: &str, archived: bool) -> String {
+    format!(r#"{{
+        "xmpp_jid": "{0}_{1}@conf.hipchat.com", "name": "{1}", "id": {0}, "created": "2016-01-01T00:00:00+00:00",
+        "is_archived": {2}, "privacy": "public", "is_guest_accessible": false, "topic": "t",
+        "avatar_url": null, "guest_access_url": null,
+        "owner": {{"id": 5, "name": "Ada", "mention_name": "ada", "links": {{"self": "https://api.hipchat.com/v2/user/5"}}}},
+        "statistics": {{"links": {{"self": "https://api.hipchat.com/v2/room/{0}/statistics"}}}},
+        "links": {{"self": "https://api.hipchat.com/v2/room/{0}", "webhooks": "w", "participants": "p"}}
+    }}"#, id, name, archived)
+}
+
+/// A notification from `CI`, as found in room history
+pub fn message(id: &str, date: &str, text: &str) -> String {
+    format!(r#"{{"id": "{}", "date": "{}", "message": {}, "type": "notification", "from": "CI", "mentions": []}}"#,
+            id, date, serde_json::to_string(text).unwrap())
+}
+
+/// A `200 OK` response with a page of room history made of `messages`
+pub fn history(messages: &[String]) -> String {
+    json(&format!(r#"{{"items": [{}], "links": {{"self": "https://api.hipchat.com/v2/room/1/history"}}}}"#, messages.join(",")))
+}
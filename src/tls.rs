@@ -0,0 +1,99 @@
+//! Selection of the TLS implementation used by `Client`
+//!
+//! The backend is chosen at compile time with the `native-tls` (default),
+//! `rustls` or `no-tls` cargo features. `no-tls` takes precedence over the
+//! other two, and `rustls` over `native-tls`. At least one must be enabled.
+
+use std::io;
+
+use hyper;
+use hyper::net::{HttpStream, SslClient};
+
+#[cfg(any(feature = "native-tls", feature = "rustls", feature = "no-tls"))]
+use error::Error;
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub enum RootCertificate {
+    Pem(Vec<u8>),
+    Der(Vec<u8>)
+}
+
+#[cfg(not(any(feature = "native-tls", feature = "rustls", feature = "no-tls")))]
+compile_error!("enable one of the native-tls, rustls or no-tls features");
+
+#[cfg(all(feature = "rustls", not(feature = "no-tls")))]
+pub type TlsClient = ::hyper_sync_rustls::TlsClient;
+
+#[cfg(all(feature = "native-tls", not(feature = "rustls"), not(feature = "no-tls")))]
+pub type TlsClient = ::hyper_native_tls::NativeTlsClient;
+
+#[cfg(feature = "no-tls")]
+pub type TlsClient = NoTls;
+
+/// Whether this build is able to make HTTPS requests
+pub fn is_enabled() -> bool {
+    cfg!(not(feature = "no-tls"))
+}
+
+#[cfg(all(feature = "rustls", not(feature = "no-tls")))]
+pub fn tls_client(root_certificates: &[RootCertificate]) -> Result<TlsClient, Error> {
+    use std::sync::Arc;
+    use rustls::{Certificate, ClientConfig, ClientSessionMemoryCache};
+
+    let mut config = ClientConfig::new();
+    config.set_persistence(ClientSessionMemoryCache::new(64));
+    config.root_store.add_server_trust_anchors(&::webpki_roots::TLS_SERVER_ROOTS);
+    for root_certificate in root_certificates {
+        match *root_certificate {
+            RootCertificate::Pem(ref pem) => {
+                let (added, _) = try!(config.root_store.add_pem_file(&mut io::Cursor::new(pem))
+                    .map_err(|_| Error::Tls("invalid PEM root certificate".into())));
+                if added == 0 {
+                    return Err(Error::Tls("no usable certificates in PEM root certificate".into()));
+                }
+            }
+            RootCertificate::Der(ref der) => {
+                try!(config.root_store.add(&Certificate(der.clone()))
+                    .map_err(|e| Error::Tls(format!("invalid DER root certificate: {:?}", e).into())));
+            }
+        }
+    }
+    Ok(::hyper_sync_rustls::TlsClient { cfg: Arc::new(config) })
+}
+
+#[cfg(all(feature = "native-tls", not(feature = "rustls"), not(feature = "no-tls")))]
+pub fn tls_client(root_certificates: &[RootCertificate]) -> Result<TlsClient, Error> {
+    use hyper_native_tls::native_tls::{Certificate, TlsConnector};
+
+    let mut tls = try!(TlsConnector::builder().map_err(|e| Error::Tls(Box::new(e))));
+    for root_certificate in root_certificates {
+        let certificate = match *root_certificate {
+            RootCertificate::Pem(ref pem) => Certificate::from_pem(pem),
+            RootCertificate::Der(ref der) => Certificate::from_der(der)
+        };
+        let certificate = try!(certificate.map_err(|e| Error::Tls(Box::new(e))));
+        try!(tls.add_root_certificate(certificate).map_err(|e| Error::Tls(Box::new(e))));
+    }
+    let connector = try!(tls.build().map_err(|e| Error::Tls(Box::new(e))));
+    Ok(TlsClient::from(connector))
+}
+
+#[cfg(feature = "no-tls")]
+pub fn tls_client(root_certificates: &[RootCertificate]) -> Result<TlsClient, Error> {
+    if !root_certificates.is_empty() {
+        return Err(Error::Tls("root certificates require the native-tls or rustls feature".into()));
+    }
+    Ok(NoTls)
+}
+
+/// An `SslClient` that refuses to wrap streams, used with the `no-tls` feature
+#[derive(Debug, Clone, Default)]
+pub struct NoTls;
+
+impl SslClient for NoTls {
+    type Stream = HttpStream;
+
+    fn wrap_client(&self, _stream: HttpStream, _host: &str) -> hyper::Result<HttpStream> {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, "HTTPS requires the native-tls or rustls feature").into())
+    }
+}
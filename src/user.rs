@@ -1,4 +1,5 @@
-use util::AppendToQueryParams;
+use error::Error;
+use util::{AppendToQueryParams, validate_max_results};
 use url::UrlQuery;
 use url::form_urlencoded::Serializer;
 
@@ -21,6 +22,57 @@ impl Default for UsersRequest {
     }
 }
 
+impl UsersRequest {
+    /// Starts from an empty request, unlike `UsersRequest::default()`
+    pub fn builder() -> UsersRequestBuilder {
+        UsersRequestBuilder::default()
+    }
+    pub fn validate(&self) -> Result<(), Error> {
+        validate_max_results(self.max_results)
+    }
+}
+
+#[derive(Debug)]
+pub struct UsersRequestBuilder {
+    request: UsersRequest
+}
+
+impl Default for UsersRequestBuilder {
+    fn default() -> Self {
+        UsersRequestBuilder {
+            request: UsersRequest {
+                start_index: None,
+                max_results: None,
+                include_guests: None,
+                include_deleted: None
+            }
+        }
+    }
+}
+
+impl UsersRequestBuilder {
+    pub fn start_index(mut self, start_index: u64) -> Self {
+        self.request.start_index = Some(start_index);
+        self
+    }
+    pub fn max_results(mut self, max_results: u64) -> Self {
+        self.request.max_results = Some(max_results);
+        self
+    }
+    pub fn include_guests(mut self, include_guests: bool) -> Self {
+        self.request.include_guests = Some(include_guests);
+        self
+    }
+    pub fn include_deleted(mut self, include_deleted: bool) -> Self {
+        self.request.include_deleted = Some(include_deleted);
+        self
+    }
+    pub fn build(self) -> Result<UsersRequest, Error> {
+        try!(self.request.validate());
+        Ok(self.request)
+    }
+}
+
 impl AppendToQueryParams for UsersRequest {
     fn append_to(&self, query: &mut Serializer<UrlQuery>){
         self.start_index.map(|start_index| query.append_pair("start-index", &start_index.to_string()));
@@ -30,7 +82,7 @@ impl AppendToQueryParams for UsersRequest {
     }
 }
 
-#[derive(Debug, Clone, Hash, Eq, PartialEq, Deserialize)]
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct UsersLinks {
     #[serde(rename = "self")]
     pub self_: String,
@@ -38,7 +90,7 @@ pub struct UsersLinks {
     pub next: Option<String>
 }
 
-#[derive(Debug, Hash, Eq, PartialEq, Deserialize)]
+#[derive(Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Users {
     #[serde(rename = "startIndex")]
     pub start_index: u64,
@@ -48,7 +100,7 @@ pub struct Users {
     pub links: UsersLinks
 }
 
-#[derive(Debug, Clone, Hash, Eq, PartialEq, Deserialize)]
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct User {
     pub name: String,
     pub mention_name: String,
@@ -56,22 +108,24 @@ pub struct User {
     pub links: UserDetailLinks
 }
 
-#[derive(Debug, Clone, Hash, Eq, PartialEq, Deserialize)]
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct UserClient {
     pub version: Option<String>,
     pub client_type: Option<String>,
 }
 
-#[derive(Debug, Clone, Hash, Eq, PartialEq, Deserialize)]
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct UserPresence {
     pub status: Option<String>,
     pub idle: Option<u64>,
+    #[serde(default)]
     pub show: String,
     pub client: Option<UserClient>,
+    #[serde(default)]
     pub is_online: bool,
 }
 
-#[derive(Debug, Clone, Hash, Eq, PartialEq, Deserialize)]
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct UserDetail {
     pub id: u64,
     pub xmpp_jid: Option<String>,
@@ -92,13 +146,28 @@ pub struct UserDetail {
     pub links: UserDetailLinks,
 }
 
-#[derive(Debug, Clone, Hash, Eq, PartialEq, Deserialize)]
+/// Request body for [Invite user](https://www.hipchat.com/docs/apiv2/method/invite_user)
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct UserInvite {
+    pub email: String,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct UserInviteResponse {
+    pub id: u64,
+    pub links: Option<UserDetailLinks>
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct UserDetailLinks {
     #[serde(rename = "self")]
     pub self_: String,
 }
 
-#[derive(Debug, Hash, Eq, PartialEq, Deserialize)]
+#[derive(Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct UserMessage {
     pub id: String,
     pub timestamp: String
@@ -153,4 +222,51 @@ mod test {
 
         assert_eq!(Some("start-index=1&max-results=10&include-guests=true&include-deleted=true"), url.query());
     }
+
+    #[test]
+    fn unit_users_request_builder() {
+        let expected = UsersRequest{ start_index: None,
+                                     max_results: Some(500),
+                                     include_guests: Some(true),
+                                     include_deleted: None };
+        let actual = UsersRequest::builder()
+            .max_results(500)
+            .include_guests(true)
+            .build()
+            .unwrap();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn unit_users_request_builder_rejects_max_results() {
+        assert!(UsersRequest::builder().max_results(0).build().is_err());
+    }
+
+    #[test]
+    fn unit_deserialize_user_detail_fixture() {
+        let user: UserDetail = serde_json::from_str(r#"{
+            "created": "2016-06-24T13:37:22+00:00",
+            "email": "ada@example.com",
+            "group": {"id": 551204, "links": {"self": "https://api.hipchat.com/v2/group/551204"}, "name": "Example"},
+            "id": 3744112,
+            "is_deleted": false,
+            "is_group_admin": true,
+            "is_guest": false,
+            "last_active": "1488386131",
+            "links": {"self": "https://api.hipchat.com/v2/user/3744112"},
+            "mention_name": "ada",
+            "name": "Ada Lovelace",
+            "photo_url": "https://secure.hipchat.com/img/silhouette_125.png",
+            "presence": {"idle": null, "is_online": false},
+            "roles": ["owner", "admin", "user"],
+            "timezone": "Europe/London",
+            "title": "",
+            "version": "6A6C2B1D",
+            "xmpp_jid": "551204_3744112@chat.hipchat.com"
+        }"#).unwrap();
+        assert_eq!(user.mention_name, "ada");
+        let presence = user.presence.unwrap();
+        assert_eq!(presence.show, "");
+        assert!(!presence.is_online);
+    }
 }
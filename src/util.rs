@@ -1,6 +1,11 @@
 use url::UrlQuery;
 use url::form_urlencoded::Serializer;
 
+use error::Error;
+
+/// Largest `max-results` value accepted by the paginated endpoints
+pub const MAX_RESULTS_LIMIT: u64 = 1000;
+
 #[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Privacy {
@@ -18,6 +23,23 @@ pub trait AppendToQueryParams {
     fn append_to(&self, query: &mut Serializer<UrlQuery>);
 }
 
+pub fn validate_max_results(max_results: Option<u64>) -> Result<(), Error> {
+    match max_results {
+        Some(n) if !(1..=MAX_RESULTS_LIMIT).contains(&n) => {
+            Err(Error::Validation(format!("max-results must be between 1 and {}, got {}", MAX_RESULTS_LIMIT, n)))
+        }
+        _ => Ok(())
+    }
+}
+
+pub fn validate_length(field: &str, value: &str, min: usize, max: usize) -> Result<(), Error> {
+    let len = value.chars().count();
+    if len < min || len > max {
+        return Err(Error::Validation(format!("{} must be between {} and {} characters, got {}", field, min, max, len)));
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -29,4 +51,13 @@ mod test {
         let expected = "\"private\"";
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn unit_validate_max_results() {
+        assert!(validate_max_results(None).is_ok());
+        assert!(validate_max_results(Some(1)).is_ok());
+        assert!(validate_max_results(Some(1000)).is_ok());
+        assert!(validate_max_results(Some(0)).is_err());
+        assert!(validate_max_results(Some(1001)).is_err());
+    }
 }
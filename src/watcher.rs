@@ -0,0 +1,352 @@
+//! Polls room history for new messages, for deployments that cannot receive webhooks
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::ops::Deref;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use hyper::status::StatusCode;
+
+use client::Client;
+use error::Error;
+use message::{Message, RecentHistoryRequest};
+use util::MAX_RESULTS_LIMIT;
+
+const DEFAULT_INTERVAL: u64 = 10;
+const DEFAULT_MAX_BACKOFF: u64 = 300;
+/// Minimum wait after HipChat reports that the rate limit was exceeded
+const RATE_LIMIT_BACKOFF: u64 = 60;
+/// Minimum wait after any other error, so a zero interval does not hammer the API
+const ERROR_BACKOFF: u64 = 1;
+
+/// A new message and the room it was posted in, as given to `RoomWatcher::new`
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub struct RoomMessage {
+    pub room: String,
+    pub message: Message
+}
+
+#[derive(Debug, Clone)]
+struct RoomState {
+    room: String,
+    last_id: Option<String>,
+    last_date: Option<String>,
+    started: bool
+}
+
+/// Watches rooms by polling recent history and yields each message once
+///
+/// The watcher is an iterator that blocks between polls. Errors are yielded
+/// as they happen and delay the next poll with exponential backoff; iteration
+/// carries on afterwards. `C` is anything that derefs to a `Client`, such as
+/// `&Client` or `Arc<Client>`.
+pub struct RoomWatcher<C: Deref<Target = Client>> {
+    client: C,
+    rooms: Vec<RoomState>,
+    interval: Duration,
+    max_backoff: Duration,
+    backoff: Option<Duration>,
+    include_backlog: bool,
+    next_poll: Instant,
+    pending: VecDeque<Result<RoomMessage, Error>>
+}
+
+impl<C: Deref<Target = Client>> RoomWatcher<C> {
+    pub fn new<T: AsRef<str>>(client: C, rooms: &[T]) -> Self {
+        RoomWatcher {
+            client: client,
+            rooms: rooms.iter().map(|room| RoomState {
+                room: room.as_ref().to_owned(),
+                last_id: None,
+                last_date: None,
+                started: false
+            }).collect(),
+            interval: Duration::from_secs(DEFAULT_INTERVAL),
+            max_backoff: Duration::from_secs(DEFAULT_MAX_BACKOFF),
+            backoff: None,
+            include_backlog: false,
+            next_poll: Instant::now(),
+            pending: VecDeque::new()
+        }
+    }
+    /// Time between polls. Defaults to 10 seconds
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+    /// Upper bound for the delay after repeated errors. Defaults to 5 minutes
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+    /// Also yield the messages already in each room's recent history when watching starts
+    pub fn include_backlog(mut self, include_backlog: bool) -> Self {
+        self.include_backlog = include_backlog;
+        self
+    }
+    /// Resumes a room after the given message, e.g. one persisted from a previous run
+    ///
+    /// `date` is the message's date, as from `last_seen_date`. If the message is
+    /// no longer in recent history or the server rejects it, only messages newer
+    /// than `date` are yielded.
+    pub fn resume_after<T: Into<String>, D: Into<String>>(mut self, room: &str, message_id: T, date: D) -> Self {
+        if let Some(state) = self.rooms.iter_mut().find(|state| state.room == room) {
+            state.last_id = Some(message_id.into());
+            state.last_date = Some(date.into());
+            state.started = true;
+        }
+        self
+    }
+    /// The id of the newest message seen in `room`
+    pub fn last_seen(&self, room: &str) -> Option<&str> {
+        self.rooms.iter()
+            .find(|state| state.room == room)
+            .and_then(|state| state.last_id.as_deref())
+    }
+    /// The date of the newest message seen in `room`
+    pub fn last_seen_date(&self, room: &str) -> Option<&str> {
+        self.rooms.iter()
+            .find(|state| state.room == room)
+            .and_then(|state| state.last_date.as_deref())
+    }
+    /// The delay currently added after errors, if any
+    pub fn current_backoff(&self) -> Option<Duration> {
+        self.backoff
+    }
+    /// Polls every room once, without waiting, and returns new messages and errors in order
+    pub fn poll(&mut self) -> Vec<Result<RoomMessage, Error>> {
+        let mut results = Vec::new();
+        let mut rate_limited = false;
+        let mut failed = false;
+
+        for state in &mut self.rooms {
+            let messages = match fetch_since_last_seen(&self.client, state) {
+                Ok(messages) => messages,
+                Err(e) => {
+                    if let Error::HttpStatus(StatusCode::TooManyRequests) = e {
+                        rate_limited = true;
+                    }
+                    failed = true;
+                    results.push(Err(e));
+                    continue;
+                }
+            };
+
+            let new_messages = new_messages(state, messages);
+            let emit = state.started || self.include_backlog;
+            state.started = true;
+            if emit {
+                for message in new_messages {
+                    results.push(Ok(RoomMessage { room: state.room.clone(), message: message }));
+                }
+            }
+        }
+
+        self.backoff = if failed {
+            let doubled = self.backoff.map(|backoff| backoff * 2).unwrap_or(self.interval);
+            let floor = Duration::from_secs(if rate_limited { RATE_LIMIT_BACKOFF } else { ERROR_BACKOFF });
+            let backoff = cmp::min(cmp::max(doubled, floor), self.max_backoff);
+            Some(cmp::max(backoff, Duration::from_secs(ERROR_BACKOFF)))
+        } else {
+            None
+        };
+        self.next_poll = Instant::now() + self.interval + self.backoff.unwrap_or_default();
+        results
+    }
+}
+
+impl<C: Deref<Target = Client> + Send + 'static> RoomWatcher<C> {
+    /// Watches on a background thread, which stops once the receiver is dropped
+    pub fn spawn(self) -> Receiver<Result<RoomMessage, Error>> {
+        let (tx, rx) = channel();
+        thread::spawn(move || {
+            for item in self {
+                if tx.send(item).is_err() {
+                    break;
+                }
+            }
+        });
+        rx
+    }
+}
+
+impl<C: Deref<Target = Client>> Iterator for RoomWatcher<C> {
+    type Item = Result<RoomMessage, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.pending.pop_front() {
+                return Some(item);
+            }
+            let now = Instant::now();
+            if now < self.next_poll {
+                thread::sleep(self.next_poll - now);
+            }
+            let results = self.poll();
+            self.pending.extend(results);
+        }
+    }
+}
+
+/// Fetches recent history from the room's last seen message on, paging through bursts
+/// larger than one response
+///
+/// If the server rejects the last seen id, e.g. because it has aged out of history,
+/// the latest messages are fetched instead and `new_messages` filters them by date.
+fn fetch_since_last_seen(client: &Client, state: &RoomState) -> Result<Vec<Message>, Error> {
+    let mut req = RecentHistoryRequest {
+        max_results: Some(MAX_RESULTS_LIMIT),
+        not_before: state.last_id.clone(),
+        ..RecentHistoryRequest::default()
+    };
+    let mut messages: Vec<Message> = Vec::new();
+    loop {
+        let page = match client.get_recent_history_with(&state.room, Some(&req)) {
+            Ok(page) => page.items,
+            Err(Error::HttpStatus(StatusCode::BadRequest)) if req.not_before.is_some() && messages.is_empty() => {
+                req.not_before = None;
+                continue;
+            }
+            Err(e) => return Err(e)
+        };
+        let full = page.len() as u64 >= MAX_RESULTS_LIMIT;
+        let oldest = req.not_before.take();
+        // Later pages start with the previous page's newest message
+        let repeated = if messages.is_empty() { None } else { oldest.as_ref() };
+        messages.extend(page.into_iter().filter(|message| Some(&message.id) != repeated));
+
+        // Without `not-before` the response is the latest messages, which cannot be paged forward
+        let newest = messages.last().map(|message| message.id.clone());
+        if !full || oldest.is_none() || newest == oldest {
+            return Ok(messages);
+        }
+        req.not_before = newest;
+    }
+}
+
+/// Drops messages at or before the last seen one and advances the room's position
+fn new_messages(state: &mut RoomState, messages: Vec<Message>) -> Vec<Message> {
+    let start = state.last_id.as_ref()
+        .and_then(|last_id| messages.iter().position(|message| &message.id == last_id))
+        .map(|i| i + 1);
+    let new_messages: Vec<Message> = match (start, state.last_date.as_ref()) {
+        (Some(start), _) => messages.into_iter().skip(start).collect(),
+        (None, Some(last_date)) => messages.into_iter().filter(|message| &message.date > last_date).collect(),
+        (None, None) => messages
+    };
+    if let Some(newest) = new_messages.last() {
+        state.last_id = Some(newest.id.clone());
+        state.last_date = Some(newest.date.clone());
+    }
+    new_messages
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_server::{self, TestServer};
+
+    fn history(ids: &[&str]) -> String {
+        let messages: Vec<String> = ids.iter()
+            .map(|id| test_server::message(id, &format!("2017-03-20T12:00:0{}.000000+00:00", id), "hi"))
+            .collect();
+        test_server::history(&messages)
+    }
+
+    fn ids(results: Vec<Result<RoomMessage, Error>>) -> Vec<String> {
+        results.into_iter().map(|result| result.unwrap().message.id).collect()
+    }
+
+    #[test]
+    fn unit_watcher_emits_only_new_messages() {
+        let server = TestServer::new(vec![history(&["1", "2"]), history(&["2", "3", "4"]), history(&["4"])]);
+        let client = test_server::client(&server);
+        let mut watcher = RoomWatcher::new(&client, &["Ops"]).interval(Duration::from_secs(0));
+
+        assert!(watcher.poll().is_empty());
+        assert_eq!(ids(watcher.poll()), vec!["3", "4"]);
+        assert!(watcher.poll().is_empty());
+        assert_eq!(watcher.last_seen("Ops"), Some("4"));
+
+        server.requests.recv().unwrap();
+        assert_eq!(server.requests.recv().unwrap().path, "/v2/room/Ops/history/latest?max-results=1000&not-before=2");
+    }
+
+    #[test]
+    fn unit_watcher_backs_off_on_errors() {
+        let server = TestServer::new(vec![
+            test_server::response("429 Too Many Requests", &[], ""),
+            test_server::response("500 Internal Server Error", &[], ""),
+            history(&["1"])
+        ]);
+        let client = test_server::client(&server);
+        let mut watcher = RoomWatcher::new(&client, &["Ops"])
+            .interval(Duration::from_secs(1))
+            .max_backoff(Duration::from_secs(90))
+            .include_backlog(true);
+
+        assert!(watcher.poll()[0].is_err());
+        assert_eq!(watcher.current_backoff(), Some(Duration::from_secs(60)));
+        assert!(watcher.poll()[0].is_err());
+        assert_eq!(watcher.current_backoff(), Some(Duration::from_secs(90)));
+        assert_eq!(ids(watcher.poll()), vec!["1"]);
+        assert_eq!(watcher.current_backoff(), None);
+    }
+
+    #[test]
+    fn unit_watcher_backs_off_with_zero_interval() {
+        let server = TestServer::new(vec![test_server::response("500 Internal Server Error", &[], "")]);
+        let client = test_server::client(&server);
+        let mut watcher = RoomWatcher::new(&client, &["Ops"])
+            .interval(Duration::from_secs(0))
+            .max_backoff(Duration::from_secs(0));
+
+        assert!(watcher.poll()[0].is_err());
+        assert_eq!(watcher.current_backoff(), Some(Duration::from_secs(ERROR_BACKOFF)));
+    }
+
+    #[test]
+    fn unit_watcher_pages_through_full_responses() {
+        let first: Vec<String> = (1..1001).map(|id| id.to_string()).collect();
+        let first: Vec<&str> = first.iter().map(String::as_str).collect();
+        let server = TestServer::new(vec![history(&first), history(&["1000", "1001", "1002"])]);
+        let client = test_server::client(&server);
+        let mut watcher = RoomWatcher::new(&client, &["Ops"])
+            .resume_after("Ops", "1", "2017-03-20T12:00:01.000000+00:00");
+
+        let ids = ids(watcher.poll());
+        assert_eq!(ids.len(), 1001);
+        assert_eq!((ids[0].as_str(), ids[1000].as_str()), ("2", "1002"));
+        assert_eq!(watcher.last_seen("Ops"), Some("1002"));
+
+        assert_eq!(server.requests.recv().unwrap().path, "/v2/room/Ops/history/latest?max-results=1000&not-before=1");
+        assert_eq!(server.requests.recv().unwrap().path, "/v2/room/Ops/history/latest?max-results=1000&not-before=1000");
+    }
+
+    #[test]
+    fn unit_watcher_falls_back_to_date_when_not_before_is_rejected() {
+        let server = TestServer::new(vec![test_server::response("400 Bad Request", &[], ""), history(&["3", "4", "5"])]);
+        let client = test_server::client(&server);
+        let mut watcher = RoomWatcher::new(&client, &["Ops"])
+            .resume_after("Ops", "2", "2017-03-20T12:00:03.000000+00:00");
+
+        assert_eq!(ids(watcher.poll()), vec!["4", "5"]);
+        assert_eq!(watcher.current_backoff(), None);
+
+        assert_eq!(server.requests.recv().unwrap().path, "/v2/room/Ops/history/latest?max-results=1000&not-before=2");
+        assert_eq!(server.requests.recv().unwrap().path, "/v2/room/Ops/history/latest?max-results=1000");
+    }
+
+    #[test]
+    fn unit_watcher_resumes_after_aged_out_message() {
+        let server = TestServer::new(vec![history(&["3", "4", "5"])]);
+        let client = test_server::client(&server);
+        let mut watcher = RoomWatcher::new(&client, &["Ops"])
+            .resume_after("Ops", "2", "2017-03-20T12:00:03.000000+00:00");
+
+        assert_eq!(ids(watcher.poll()), vec!["4", "5"]);
+        assert_eq!(watcher.last_seen_date("Ops"), Some("2017-03-20T12:00:05.000000+00:00"));
+    }
+}